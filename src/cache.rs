@@ -1,55 +1,181 @@
-use crate::config::{Config, EnvAlias, EnvVariable, EnvVariableStruct};
-use anyhow::{Context, Result};
+use crate::config::{Config, EnvAlias, EnvVariable, EnvVariableStruct, LoadFromEntry};
+use crate::list::{bin_variable, merge_variable};
+use crate::utils::{expand_path, get_config_hash, is_glob, resolve_glob, resolve_path};
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
 
 pub type DirCacheMap = HashMap<String, DirCache>;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct DirCache {
     pub variables: Vec<EnvVariable>,
     pub run: Vec<String>,
     pub aliases: Vec<EnvAlias>,
-    pub load_from: Vec<String>,
+    pub load_from: Vec<LoadFromEntry>,
+    #[serde(default)]
+    pub on_leave: Vec<String>,
+    #[serde(default)]
+    pub bin: Vec<String>,
+}
+
+/// Bumped whenever the layout of the cache changes so caches written by
+/// older versions of cdwe are rebuilt instead of being misread
+const CACHE_VERSION: u32 = 10;
+
+/// A directory path that matches more than one directory
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum PathPattern {
+    /// Shell style glob, `*` never matches across a `/`
+    Glob(String),
+    /// Regular expression that has to match the whole path
+    Regex(String),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PatternCache {
+    pub pattern: PathPattern,
+    pub dir: DirCache,
+}
+
+impl std::fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathPattern::Glob(glob) => write!(f, "{}", glob),
+            PathPattern::Regex(re) => write!(f, "path_regex {}", re),
+        }
+    }
+}
+
+/// A compiled [`PathPattern`]
+enum PathMatcher {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+impl PathMatcher {
+    fn new(pattern: &PathPattern) -> Result<Self> {
+        match pattern {
+            PathPattern::Glob(glob) => Ok(PathMatcher::Glob(
+                glob::Pattern::new(glob)
+                    .with_context(|| format!("Invalid glob in directory path: {}", glob))?,
+            )),
+            PathPattern::Regex(re) => Ok(PathMatcher::Regex(
+                regex::Regex::new(&format!("^(?:{})$", re))
+                    .with_context(|| format!("Invalid regex in directory path_regex: {}", re))?,
+            )),
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            PathMatcher::Glob(glob) => glob.matches_with(
+                path,
+                glob::MatchOptions {
+                    require_literal_separator: true,
+                    ..Default::default()
+                },
+            ),
+            PathMatcher::Regex(re) => re.is_match(path),
+        }
+    }
 }
 
 /// Cache is optimized for speed of lookup
 /// Config is optimized for readability and usability for the user
-/// Cache is stored in a json file ussually ~/.cdwe_cache.json
+/// Cache is stored in a json file ussually ~/.cache/cdwe/cache.json
 #[derive(Serialize, Deserialize)]
 pub struct Cache {
+    #[serde(default)]
+    version: u32,
     pub shell: String,
     pub hash: String,
+    #[serde(default)]
+    pub resolve_symlinks: bool,
     values: DirCacheMap,
-}
-
-/// Inserts any cdwe path environment variables into itself and returns
-/// updated path
-fn insert_env_var_into_path(re: &regex::Regex, path: &str) -> String {
-    re.replace_all(path, |caps: &regex::Captures| {
-        if let Some(value) = std::env::var(&caps[1]).ok() {
-            value // If the env var exists, replace with its value
-        } else {
-            caps[0].to_string() // If not, keep the original text
-        }
-    })
-    .to_string()
+    /// Entries whose path matches more than one directory, kept in the order
+    /// they were first configured
+    #[serde(default)]
+    patterns: Vec<PatternCache>,
+    /// Include patterns of the config, the files they match are part of the
+    /// hash
+    #[serde(default)]
+    includes: Vec<String>,
+    /// Profiles that were active when the cache was built
+    #[serde(default)]
+    profiles: Vec<String>,
 }
 
 impl Cache {
     pub fn new(shell: String, hash: String, values: DirCacheMap) -> Self {
         Cache {
+            version: CACHE_VERSION,
             shell,
             hash,
+            resolve_symlinks: false,
             values,
+            patterns: vec![],
+            includes: vec![],
+            profiles: vec![],
         }
     }
 
-    pub fn from_config(config: &Config, config_hash: &str) -> Self {
-        let mut values: DirCacheMap = HashMap::new();
+    /// Normalizes a directory the same way configured paths are, so it can
+    /// be looked up in the cache
+    pub fn resolve_path(&self, path: &str) -> String {
+        resolve_path(path, self.resolve_symlinks)
+    }
+
+    /// Returns the entry for a configured path, creating it if needed
+    /// Literal paths go into the exact lookup map, globs into the patterns
+    fn entry(&mut self, path: &str) -> &mut DirCache {
+        if is_glob(path) {
+            let pattern = resolve_glob(path, self.resolve_symlinks);
+            self.pattern_entry(PathPattern::Glob(pattern))
+        } else {
+            let path = self.resolve_path(path);
+            self.values.entry(path).or_default()
+        }
+    }
+
+    fn pattern_entry(&mut self, pattern: PathPattern) -> &mut DirCache {
+        let index = match self.patterns.iter().position(|p| p.pattern == pattern) {
+            Some(index) => index,
+            None => {
+                self.patterns.push(PatternCache {
+                    pattern,
+                    dir: DirCache::default(),
+                });
+                self.patterns.len() - 1
+            }
+        };
+
+        &mut self.patterns[index].dir
+    }
+
+    /// Entries configured for a literal path
+    pub fn dirs(&self) -> impl Iterator<Item = (&String, &DirCache)> {
+        self.values.iter()
+    }
 
-        // Captures the content within {{}}
-        let re = regex::Regex::new(r"\{\{(.*?)\}\}").unwrap();
+    /// Entries configured for a glob or a regex
+    pub fn patterns(&self) -> impl Iterator<Item = (&PathPattern, &DirCache)> {
+        self.patterns.iter().map(|p| (&p.pattern, &p.dir))
+    }
+
+    pub fn from_config(config: &Config, config_hash: &str) -> Result<Self> {
+        let shell = match &config.config {
+            Some(global_config) => global_config.shell.clone().unwrap_or("bash".to_string()),
+            None => "bash".to_string(),
+        };
+
+        let mut cache = Cache::new(shell, config_hash.to_string(), HashMap::new());
+        cache.resolve_symlinks = config
+            .config
+            .as_ref()
+            .and_then(|c| c.resolve_symlinks)
+            .unwrap_or(false);
 
         for directory in &config.directories {
             let variables: Vec<EnvVariable> = match &directory.vars {
@@ -58,6 +184,7 @@ impl Cache {
                     .map(|(name, value)| EnvVariable {
                         name: name.clone(),
                         value: value.clone(),
+                        ..Default::default()
                     })
                     .collect(),
                 Some(EnvVariableStruct::EnvVariableVec(dir_env_variable)) => {
@@ -72,63 +199,211 @@ impl Cache {
                 aliases.extend(dir_aliases.clone());
             }
 
-            let load_from = directory.load_from.clone().unwrap_or(vec![]);
+            let load_from = directory
+                .load_from
+                .iter()
+                .flatten()
+                .map(|file| {
+                    let mut entry = LoadFromEntry::from(file.clone());
+                    entry.path = expand_path(&entry.path)?;
+                    Ok(entry)
+                })
+                .collect::<Result<Vec<LoadFromEntry>>>()?;
 
-            let run = directory.run.clone().unwrap_or(vec![]);
+            let run = directory.run.clone().unwrap_or_default();
 
-            let dir_cache: DirCache = DirCache {
-                variables,
-                run,
-                load_from: load_from.clone(),
-                aliases,
+            let on_leave = directory.on_leave.clone().unwrap_or_default();
+
+            let dir_cache = match &directory.path_regex {
+                Some(path_regex) => cache.pattern_entry(PathPattern::Regex(path_regex.clone())),
+                None if directory.path.is_empty() => {
+                    return Err(anyhow!("directory is missing a path or path_regex"))
+                }
+                None => cache.entry(&expand_path(&directory.path)?),
             };
+            dir_cache.variables.extend(variables);
+            dir_cache.run.extend(run);
+            dir_cache.on_leave.extend(on_leave);
+            dir_cache.load_from.extend(load_from);
+            dir_cache.aliases.extend(aliases);
+            let bin = directory
+                .bin
+                .iter()
+                .flatten()
+                .map(|entry| expand_path(entry))
+                .collect::<Result<Vec<String>>>()?;
+            dir_cache.bin.extend(bin);
+        }
 
-            let result = insert_env_var_into_path(&re, directory.path.as_str());
-            values.insert(result, dir_cache);
+        // Top level tables apply one entry to many directories, they are
+        // folded into the same per directory entries as [[directory]]
+        for variable in config.variables.iter().flatten() {
+            for dir in &variable.dirs {
+                let result = expand_path(dir)?;
+                cache.entry(&result).variables.push(EnvVariable {
+                    name: variable.name.clone(),
+                    value: variable.value.clone(),
+                    ..Default::default()
+                });
+            }
         }
 
-        let shell = match &config.config {
-            Some(global_config) => global_config.shell.clone().unwrap_or("bash".to_string()),
-            None => "bash".to_string(),
-        };
+        for command in config.commands.iter().flatten() {
+            for dir in &command.dirs {
+                let result = expand_path(dir)?;
+                cache.entry(&result).run.push(command.run.clone());
+            }
+        }
+
+        for file in config.files.iter().flatten() {
+            for dir in &file.dirs {
+                let result = expand_path(dir)?;
+                cache.entry(&result).load_from.push(LoadFromEntry {
+                    path: expand_path(&file.load_from)?,
+                    optional: file.optional.unwrap_or(false),
+                    encrypted: file.encrypted.unwrap_or(false),
+                });
+            }
+        }
+
+        for alias in config.aliases.iter().flatten() {
+            for dir in &alias.paths {
+                let result = expand_path(dir)?;
+                cache.entry(&result).aliases.push(EnvAlias {
+                    name: alias.name.clone(),
+                    commands: alias.commands.clone(),
+                    description: alias.description.clone(),
+                });
+            }
+        }
+
+        // Surface invalid patterns now rather than on every lookup
+        for pattern in &cache.patterns {
+            PathMatcher::new(&pattern.pattern)?;
+        }
+
+        Ok(cache)
+    }
+
+    /// Returns every configured entry that applies to `path`, ordered from
+    /// the farthest ancestor to the path itself, along with the directory
+    /// each entry was configured for
+    ///
+    /// When several entries match the same directory, patterns apply in the
+    /// order they were configured and the exact path entry applies last, so
+    /// a literal path always overrides a pattern
+    pub fn get_chain(&self, path: &str) -> Vec<(String, &DirCache)> {
+        let matchers: Vec<(PathMatcher, &DirCache)> = self
+            .patterns
+            .iter()
+            .filter_map(|p| Some((PathMatcher::new(&p.pattern).ok()?, &p.dir)))
+            .collect();
+
+        let ancestors: Vec<&str> = Path::new(path)
+            .ancestors()
+            .filter_map(|ancestor| ancestor.to_str())
+            .collect();
+
+        let mut chain: Vec<(String, &DirCache)> = vec![];
+
+        for ancestor in ancestors.into_iter().rev() {
+            for (matcher, dir) in &matchers {
+                if matcher.matches(ancestor) {
+                    chain.push((ancestor.to_string(), dir));
+                }
+            }
+
+            if let Some(dir) = self.values.get(ancestor) {
+                chain.push((ancestor.to_string(), dir));
+            }
+        }
 
-        Cache::new(shell, config_hash.to_string(), values)
+        chain
     }
 
-    pub fn get(&self, path: &str) -> Option<&DirCache> {
-        self.values.get(path)
+    /// Resolves the entry for `path` by walking its ancestors
+    /// Variables, aliases and bin dirs are inherited from every ancestor, with
+    /// nearer entries overriding farther ones
+    /// Env files and commands only apply to the exact matching directory
+    pub fn get(&self, path: &str) -> Option<DirCache> {
+        let chain = self.get_chain(path);
+
+        if chain.is_empty() {
+            return None;
+        }
+
+        let mut resolved = DirCache::default();
+
+        for (dir_path, dir) in chain {
+            for var in &dir.variables {
+                merge_variable(&mut resolved.variables, var.clone());
+            }
+
+            // Bin dirs are resolved against the directory each entry matched,
+            // which for patterns is only known here
+            if !dir.bin.is_empty() {
+                merge_variable(&mut resolved.variables, bin_variable(&dir_path, &dir.bin));
+            }
+
+            for alias in &dir.aliases {
+                resolved.aliases.retain(|a| a.name != alias.name);
+                resolved.aliases.push(alias.clone());
+            }
+
+            if dir_path == path {
+                resolved.run.extend(dir.run.iter().cloned());
+                resolved.load_from.extend(dir.load_from.iter().cloned());
+            }
+        }
+
+        Some(resolved)
     }
 }
 
 /// If a cache doesn't exist create one
-/// If a cache exists but the config has changed we create a new cache
+/// If a cache exists but the config, any file it includes or the active
+/// profiles have changed we create a new cache
 /// Returns the cache and a boolean indicating if the cache was created
 pub fn get_or_create_cache(
     cache_content: Option<&str>,
     config_content: &str,
-    config_hash: &str,
+    config_path: &str,
+    profiles: &[String],
 ) -> Result<(Cache, bool)> {
-    if let Some(cache_content) = cache_content {
-        let previous_cache: Cache = serde_json::from_str(cache_content)?;
-
-        if previous_cache.hash == config_hash {
-            return Ok((previous_cache, false));
+    // A cache that can't be read, like one with the layout of an older
+    // version, is stale and gets rebuilt like any other
+    let previous_cache =
+        cache_content.and_then(|content| serde_json::from_str::<Cache>(content).ok());
+    if let Some(previous_cache) = previous_cache {
+        // The includes are only known from the previous cache, if any of the
+        // files they matched is gone the cache is rebuilt to report it
+        if previous_cache.version == CACHE_VERSION && previous_cache.profiles == profiles {
+            let config_hash = get_config_hash(config_content, &previous_cache.includes);
+            if config_hash.is_ok_and(|hash| hash == previous_cache.hash) {
+                return Ok((previous_cache, false));
+            }
         }
     }
 
-    let config = Config::from_str(config_content).context("failed to parse config")?;
+    let (mut config, includes) = Config::from_str_with_includes(config_content, config_path)
+        .context("failed to parse config")?;
+    config.apply_profiles(profiles)?;
+    let config_hash = get_config_hash(config_content, &includes)?;
 
-    Ok((Cache::from_config(&config, config_hash), true))
+    let mut cache = Cache::from_config(&config, &config_hash)?;
+    cache.includes = includes;
+    cache.profiles = profiles.to_vec();
+    Ok((cache, true))
 }
 
-pub fn write_cache(cache: &Cache, home: &str) -> Result<()> {
+pub fn write_cache(cache: &Cache, cache_path: &str) -> Result<()> {
     let cache_content = serde_json::to_string(cache)?;
-    let home = home.to_string();
+    let cache_path = cache_path.to_string();
     tokio::spawn(async move {
-        std::fs::write(
-            home.to_string() + "/.cdwe_cache.json",
-            cache_content.as_bytes(),
-        )
+        if let Some(parent) = Path::new(&cache_path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&cache_path, cache_content.as_bytes())
     });
 
     Ok(())
@@ -136,28 +411,378 @@ pub fn write_cache(cache: &Cache, home: &str) -> Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use super::insert_env_var_into_path;
+    use super::Cache;
+    use crate::config::Config;
+
+    #[test]
+    fn test_get_inherits_from_ancestors() {
+        let config = Config::from_str(
+            r#"
+            [[directory]]
+            path = "/dev/project"
+            vars = { "IS_DEBUG" = "true", "NAME" = "project" }
+            load_from = [".env"]
+            run = ["git fetch -p"]
+            aliases = [{ name = "build", commands = ["cargo build"] }]
+
+            [[directory]]
+            path = "/dev/project/src"
+            vars = { "NAME" = "src" }
+            "#,
+        )
+        .unwrap();
+        let cache = Cache::from_config(&config, "hash").unwrap();
+
+        assert!(cache.get("/dev").is_none());
+
+        let project = cache.get("/dev/project").unwrap();
+        assert_eq!(project.run, vec!["git fetch -p"]);
+        assert_eq!(project.load_from[0].path, ".env");
+
+        let src = cache.get("/dev/project/src").unwrap();
+        let value_of = |name: &str| {
+            src.variables
+                .iter()
+                .find(|v| v.name == name)
+                .map(|v| v.value.clone())
+        };
+        assert_eq!(value_of("IS_DEBUG").as_deref(), Some("true"));
+        assert_eq!(value_of("NAME").as_deref(), Some("src"));
+        assert_eq!(src.aliases.len(), 1);
+        assert!(src.run.is_empty());
+        assert!(src.load_from.is_empty());
+
+        let nested = cache.get("/dev/project/src/bin").unwrap();
+        assert_eq!(nested.variables.len(), 2);
+    }
+
+    #[test]
+    fn test_from_config_folds_top_level_tables() {
+        let config = Config::from_str(
+            r#"
+            [[directory]]
+            path = "/dev/project"
+            vars = { "IS_DEBUG" = "true" }
+
+            [[env_variable]]
+            name = "ENV_VAR"
+            value = "shared"
+            dirs = ["/dev/project", "/dev/other"]
+
+            [[command]]
+            run = "git fetch -p"
+            dirs = ["/dev/project"]
+
+            [[env_file]]
+            load_from = ".env"
+            dirs = ["/dev/other"]
+
+            [[alias]]
+            name = "build"
+            commands = ["cargo build"]
+            dirs = ["/dev/project"]
+            "#,
+        )
+        .unwrap();
+        let cache = Cache::from_config(&config, "hash").unwrap();
+
+        let project = cache.get("/dev/project").unwrap();
+        assert_eq!(project.variables.len(), 2);
+        assert_eq!(project.run, vec!["git fetch -p"]);
+        assert_eq!(project.aliases[0].name, "build");
+
+        let other = cache.get("/dev/other").unwrap();
+        assert_eq!(other.variables[0].value, "shared");
+        assert_eq!(other.load_from[0].path, ".env");
+    }
+
+    #[test]
+    fn test_get_resolves_bin_dirs() {
+        let config = Config::from_str(
+            r#"
+            [[directory]]
+            path = "/work/*"
+            bin = ["./scripts"]
+
+            [[directory]]
+            path = "/work/app/frontend"
+            bin = ["node_modules/.bin", "../tools", "/opt/bin"]
+            "#,
+        )
+        .unwrap();
+        let cache = Cache::from_config(&config, "hash").unwrap();
+
+        let frontend = cache.get("/work/app/frontend").unwrap();
+        assert_eq!(frontend.variables.len(), 1);
+        assert_eq!(frontend.variables[0].name, "PATH");
+        assert_eq!(
+            frontend.variables[0].prepend,
+            Some(vec![
+                "/work/app/frontend/node_modules/.bin".to_string(),
+                "/work/app/tools".to_string(),
+                "/opt/bin".to_string(),
+                "/work/app/scripts".to_string(),
+            ])
+        );
+    }
 
     #[test]
-    fn test_insert_env_var_into_path() {
-        let re = regex::Regex::new(r"\{\{(.*?)\}\}").unwrap();
-        std::env::set_var("TEST_HOME", "/home/user");
-        std::env::set_var("TEST_NAME", "testing");
+    fn test_get_matches_patterns() {
+        let config = Config::from_str(
+            r#"
+            [[directory]]
+            path = "/work/*/services/*"
+            vars = { "KIND" = "glob", "SERVICE" = "true" }
+
+            [[directory]]
+            path_regex = "/work/[a-z]+/services/api"
+            vars = { "KIND" = "regex" }
+
+            [[directory]]
+            path = "/work/team/services/api"
+            vars = { "KIND" = "literal" }
+            "#,
+        )
+        .unwrap();
+        let cache = Cache::from_config(&config, "hash").unwrap();
+        let kind_of = |path: &str| {
+            cache.get(path).and_then(|dir| {
+                dir.variables
+                    .iter()
+                    .rev()
+                    .find(|v| v.name == "KIND")
+                    .map(|v| v.value.clone())
+            })
+        };
+
+        assert_eq!(
+            kind_of("/work/team/services/api").as_deref(),
+            Some("literal")
+        );
         assert_eq!(
-            insert_env_var_into_path(&re, "{{TEST_HOME}}/testing"),
-            "/home/user/testing"
+            kind_of("/work/other/services/api").as_deref(),
+            Some("regex")
         );
+        assert_eq!(kind_of("/work/other/services/web").as_deref(), Some("glob"));
         assert_eq!(
-            insert_env_var_into_path(&re, "{{TEST_HOME}}/{{TEST_NAME}}"),
-            "/home/user/testing"
+            kind_of("/work/other/services/web/src").as_deref(),
+            Some("glob")
         );
+        assert_eq!(kind_of("/work/a/b/services/web"), None);
+        assert_eq!(kind_of("/work/other/services"), None);
+    }
+
+    #[test]
+    fn test_get_normalizes_paths() {
+        let config = Config::from_str(
+            r#"
+            [[directory]]
+            path = "/dev/project/"
+            vars = { "IS_DEBUG" = "true" }
+
+            [[directory]]
+            path = "/dev/other/../pattern/*/"
+            vars = { "IS_PATTERN" = "true" }
+            "#,
+        )
+        .unwrap();
+        let cache = Cache::from_config(&config, "hash").unwrap();
+
+        let project = cache.resolve_path("/dev/./project/src/../");
+        assert_eq!(project, "/dev/project");
+        assert!(cache.get(&project).is_some());
+        assert!(cache.get(&cache.resolve_path("/dev/pattern/a/")).is_some());
+    }
+
+    #[test]
+    fn test_from_config_load_from_entries() {
+        let config = Config::from_str(
+            r#"
+            [[directory]]
+            path = "/dev/project"
+            load_from = [".env", { path = ".env.local", optional = true }]
+
+            [[env_file]]
+            load_from = ".env.shared"
+            optional = true
+            dirs = ["/dev/project"]
+            "#,
+        )
+        .unwrap();
+        let cache = Cache::from_config(&config, "hash").unwrap();
+
+        let project = cache.get("/dev/project").unwrap();
+        let load_from: Vec<(&str, bool)> = project
+            .load_from
+            .iter()
+            .map(|f| (f.path.as_str(), f.optional))
+            .collect();
         assert_eq!(
-            insert_env_var_into_path(&re, "{{DOES_NOT_EXIST}}/{{TEST_NAME}}"),
-            "{{DOES_NOT_EXIST}}/testing"
+            load_from,
+            vec![(".env", false), (".env.local", true), (".env.shared", true)]
+        );
+    }
+
+    #[test]
+    fn test_get_or_create_cache_with_includes() {
+        use super::get_or_create_cache;
+
+        let dir = std::env::temp_dir().join("cdwe_test_includes");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let write = |name: &str, content: &str| std::fs::write(dir.join(name), content).unwrap();
+        let config_path = dir.join("config.toml");
+        let config_path = config_path.to_str().unwrap();
+
+        let config = r#"
+            include = ["work.toml", "conf.d/*.toml"]
+
+            [[directory]]
+            path = "/dev"
+            vars = { "MAIN" = "1" }
+            "#;
+        write("config.toml", config);
+        write(
+            "work.toml",
+            "include = [\"config.toml\"]\n[[env_variable]]\nname = \"WORK\"\nvalue = \"1\"\ndirs = [\"/dev\"]\n",
+        );
+        write(
+            "conf.d/a.toml",
+            "[[directory]]\npath = \"/dev/a\"\nvars = { \"A\" = \"1\" }\n",
+        );
+
+        let (cache, created) = get_or_create_cache(None, config, config_path, &[]).unwrap();
+        assert!(created);
+        let names: Vec<String> = cache
+            .get("/dev/a")
+            .unwrap()
+            .variables
+            .into_iter()
+            .map(|v| v.name)
+            .collect();
+        assert_eq!(names, vec!["MAIN", "WORK", "A"]);
+
+        let cache_content = serde_json::to_string(&cache).unwrap();
+        let (_, created) =
+            get_or_create_cache(Some(&cache_content), config, config_path, &[]).unwrap();
+        assert!(!created);
+
+        // Adding a file matched by a glob invalidates the cache
+        write(
+            "conf.d/b.toml",
+            "[[directory]]\npath = \"/dev/b\"\nvars = { \"B\" = \"1\" }\n",
         );
+        let (cache, created) =
+            get_or_create_cache(Some(&cache_content), config, config_path, &[]).unwrap();
+        assert!(created);
+        assert!(cache.get("/dev/b").is_some());
+
+        // So does editing an included file
+        let cache_content = serde_json::to_string(&cache).unwrap();
+        write("work.toml", "");
+        let (cache, created) =
+            get_or_create_cache(Some(&cache_content), config, config_path, &[]).unwrap();
+        assert!(created);
+        assert_eq!(cache.get("/dev").unwrap().variables.len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_get_or_create_cache_rebuilds_unreadable_caches() {
+        use super::get_or_create_cache;
+
+        let config = "[[directory]]\npath = \"/dev\"\nload_from = [\".env\"]\n";
+        let old_layout = r#"{"shell":"bash","hash":"x","values":{"/dev":{"variables":[],"run":[],"aliases":[],"load_from":[".env"]}}}"#;
+
+        for cache_content in [old_layout, "not json"] {
+            let (cache, created) =
+                get_or_create_cache(Some(cache_content), config, "/cdwe.toml", &[]).unwrap();
+            assert!(created);
+            assert_eq!(cache.get("/dev").unwrap().load_from[0].path, ".env");
+        }
+    }
+
+    #[test]
+    fn test_get_or_create_cache_include_cycles() {
+        use super::get_or_create_cache;
+
+        let dir = std::env::temp_dir().join("cdwe_test_include_cycles");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let config_path = dir.join("config.toml");
+        let config = "include = [\"sub/../config.toml\", \"work.toml\"]\n[[directory]]\npath = \"/dev\"\nvars = { \"MAIN\" = \"1\" }\n";
+        std::fs::write(&config_path, config).unwrap();
+        std::fs::write(
+            dir.join("work.toml"),
+            "include = [\"link.toml\"]\n[[directory]]\npath = \"/dev\"\nvars = { \"WORK\" = \"1\" }\n",
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(dir.join("work.toml"), dir.join("link.toml")).unwrap();
+
+        let (cache, _) =
+            get_or_create_cache(None, config, config_path.to_str().unwrap(), &[]).unwrap();
+        let names: Vec<String> = cache
+            .get("/dev")
+            .unwrap()
+            .variables
+            .into_iter()
+            .map(|v| v.name)
+            .collect();
+        assert_eq!(names, vec!["MAIN", "WORK"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_get_or_create_cache_with_profiles() {
+        use super::get_or_create_cache;
+
+        let config = r#"
+            [[directory]]
+            path = "/dev"
+            vars = { "ENV" = "base" }
+
+            [[profile.work.directory]]
+            path = "/dev"
+            vars = { "ENV" = "work" }
+
+            [[profile.oncall.env_variable]]
+            name = "PAGER"
+            value = "1"
+            dirs = ["/dev"]
+            "#;
+        let vars = |cache: &Cache| -> Vec<(String, String)> {
+            cache
+                .get("/dev")
+                .unwrap()
+                .variables
+                .into_iter()
+                .map(|v| (v.name, v.value))
+                .collect()
+        };
+        let profiles =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|n| n.to_string()).collect() };
+
+        let (cache, _) = get_or_create_cache(None, config, "/cdwe.toml", &[]).unwrap();
+        assert_eq!(vars(&cache), vec![("ENV".into(), "base".into())]);
+
+        // Switching profiles rebuilds the cache even though the config didn't change
+        let cache_content = serde_json::to_string(&cache).unwrap();
+        let work = profiles(&["work", "oncall"]);
+        let (cache, created) =
+            get_or_create_cache(Some(&cache_content), config, "/cdwe.toml", &work).unwrap();
+        assert!(created);
         assert_eq!(
-            insert_env_var_into_path(&re, "/home/user/testing"),
-            "/home/user/testing"
+            vars(&cache),
+            vec![("ENV".into(), "work".into()), ("PAGER".into(), "1".into())]
         );
+
+        let cache_content = serde_json::to_string(&cache).unwrap();
+        let (_, created) =
+            get_or_create_cache(Some(&cache_content), config, "/cdwe.toml", &work).unwrap();
+        assert!(!created);
+
+        assert!(get_or_create_cache(None, config, "/cdwe.toml", &profiles(&["home"])).is_err());
     }
 }
@@ -0,0 +1,698 @@
+use super::emit::{is_valid_alias_name, is_valid_var_name};
+use super::Shell;
+use crate::cache::Cache;
+use crate::config::{Config, EnvAlias, EnvVariable, LocalConfig};
+use crate::env_file::parse_env_file;
+use crate::utils::{expand_path, normalize_path};
+use anyhow::{anyhow, Result};
+use serde::de::{DeserializeOwned, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use toml::Spanned;
+
+#[derive(Debug, PartialEq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+/// Something wrong with a config file, errors break `cdwe run` while
+/// warnings are either ignored or only reported when cd'ing
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub level: Level,
+    pub message: String,
+    /// The included file the problem is in, `None` for the checked file
+    pub file: Option<String>,
+    /// The line the problem is on, when it can be traced back to one
+    pub line: Option<usize>,
+}
+
+impl Problem {
+    fn error(message: String) -> Self {
+        Problem {
+            level: Level::Error,
+            message,
+            file: None,
+            line: None,
+        }
+    }
+
+    fn warning(message: String) -> Self {
+        Problem {
+            level: Level::Warning,
+            message,
+            file: None,
+            line: None,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.level {
+            Level::Error => write!(f, "error: {}", self.message),
+            Level::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+/// A toml document reduced to where its keys and values are, so problems
+/// found once it's deserialized can be pointed at a line
+enum Node {
+    Table(Vec<(Spanned<String>, Spanned<Node>)>),
+    Array(Vec<Spanned<Node>>),
+    String(String),
+    Other,
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a toml value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+        let mut entries = vec![];
+        while let Some(key) = map.next_key()? {
+            entries.push((key, map.next_value()?));
+        }
+        Ok(Node::Table(entries))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+        let mut elements = vec![];
+        while let Some(element) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(Node::Array(elements))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Node, E> {
+        Ok(Node::String(value.to_string()))
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<Node, E> {
+        Ok(Node::Other)
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<Node, E> {
+        Ok(Node::Other)
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<Node, E> {
+        Ok(Node::Other)
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<Node, E> {
+        Ok(Node::Other)
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+impl Node {
+    /// Offset of the key or element at `path`, or of the deepest part of
+    /// `path` that exists
+    fn find(&self, path: &[String]) -> Option<usize> {
+        let (segment, rest) = path.split_first()?;
+        let (start, node) = match self {
+            Node::Table(entries) => entries
+                .iter()
+                .find(|(key, _)| key.get_ref() == segment)
+                .map(|(key, value)| (key.span().start, value.get_ref()))?,
+            Node::Array(elements) => {
+                let element = elements.get(segment.parse::<usize>().ok()?)?;
+                (element.span().start, element.get_ref())
+            }
+            _ => return None,
+        };
+
+        node.find(rest).or(Some(start))
+    }
+
+    /// Offset of the first definition of a variable or alias called `name`,
+    /// either a table with that `name` or a key of a `vars` table
+    fn find_name(&self, name: &str) -> Option<usize> {
+        match self {
+            Node::Table(entries) => entries.iter().find_map(|(key, value)| {
+                match (key.get_ref().as_str(), value.get_ref()) {
+                    ("name", Node::String(value_name)) if value_name == name => {
+                        Some(value.span().start)
+                    }
+                    ("vars", Node::Table(vars)) => vars
+                        .iter()
+                        .find(|(var, _)| var.get_ref() == name)
+                        .map(|(var, _)| var.span().start),
+                    _ => value.get_ref().find_name(name),
+                }
+            }),
+            Node::Array(elements) => elements.iter().find_map(|e| e.get_ref().find_name(name)),
+            _ => None,
+        }
+    }
+}
+
+/// A config file being checked
+struct Source {
+    /// Set for included files, problems of the checked file are reported
+    /// under its own path
+    file: Option<String>,
+    content: String,
+    /// `None` when the file doesn't parse, or uses something like dates that
+    /// can't be traced
+    tree: Option<Node>,
+    /// Number of `[[directory]]` entries of the file itself
+    directories: usize,
+}
+
+impl Source {
+    fn new(file: Option<String>, content: &str) -> Self {
+        Source {
+            file,
+            content: content.to_string(),
+            tree: toml::from_str(content).ok(),
+            directories: 0,
+        }
+    }
+
+    /// Points `problem` at this file and the line of `offset`
+    fn locate(&self, problem: Problem, offset: Option<usize>) -> Problem {
+        let line = offset
+            .and_then(|offset| self.content.get(..offset))
+            .map(|before| before.matches('\n').count() + 1);
+
+        Problem {
+            file: self.file.clone(),
+            line,
+            ..problem
+        }
+    }
+
+    fn find(&self, path: &[String]) -> Option<usize> {
+        self.tree.as_ref()?.find(path)
+    }
+
+    fn find_name(&self, name: &str) -> Option<usize> {
+        self.tree.as_ref()?.find_name(name)
+    }
+}
+
+/// Points a problem about a variable or alias at the first of `sources` that
+/// defines it
+fn locate_name(sources: &[Source], name: &str, problem: Problem) -> Problem {
+    match sources
+        .iter()
+        .find_map(|source| Some((source, source.find_name(name)?)))
+    {
+        Some((source, offset)) => source.locate(problem, Some(offset)),
+        None => problem,
+    }
+}
+
+/// Splits the path of an ignored key into the keys it's written with in
+/// toml, like `directory`, `0` and `runs`
+fn key_segments(path: &serde_ignored::Path, segments: &mut Vec<String>) {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => {}
+        Path::Seq { parent, index } => {
+            key_segments(parent, segments);
+            segments.push(index.to_string());
+        }
+        Path::Map { parent, key } => {
+            key_segments(parent, segments);
+            segments.push(key.clone());
+        }
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => key_segments(parent, segments),
+    }
+}
+
+/// Parses a toml source, collecting the keys serde ignores along the way
+/// Parse errors keep the line, column and snippet toml reports
+fn parse<T: DeserializeOwned>(source: &Source, problems: &mut Vec<Problem>) -> Option<T> {
+    let mut unknown = vec![];
+    let parsed = serde_ignored::deserialize(toml::Deserializer::new(&source.content), |path| {
+        let mut segments = vec![];
+        key_segments(&path, &mut segments);
+        unknown.push(segments)
+    });
+
+    match parsed {
+        Ok(parsed) => {
+            for segments in unknown {
+                let problem = Problem::warning(format!("unknown key {}", segments.join(".")));
+                problems.push(source.locate(problem, source.find(&segments)));
+            }
+            Some(parsed)
+        }
+        Err(err) => {
+            let problem = Problem::error(err.to_string().trim_end().to_string());
+            problems.push(source.locate(problem, None));
+            None
+        }
+    }
+}
+
+fn check_names(
+    scope: &str,
+    shell: &Shell,
+    variables: &[EnvVariable],
+    aliases: &[EnvAlias],
+    sources: &[Source],
+    problems: &mut Vec<Problem>,
+) {
+    for var in variables.iter().filter(|v| !is_valid_var_name(&v.name)) {
+        let problem = Problem::error(format!("{}: invalid variable name \"{}\"", scope, var.name));
+        problems.push(locate_name(sources, &var.name, problem));
+    }
+
+    for alias in aliases
+        .iter()
+        .filter(|a| !is_valid_alias_name(shell, &a.name))
+    {
+        let problem = Problem::error(format!("{}: invalid alias name \"{}\"", scope, alias.name));
+        problems.push(locate_name(sources, &alias.name, problem));
+    }
+}
+
+/// Warns about directories configured more than once, their entries are
+/// merged which is rarely intended
+/// The directories of `config` are those of each of `sources` in order, the
+/// warning points at the first entry that repeats a path
+fn check_duplicates(config: &Config, sources: &[Source], problems: &mut Vec<Problem>) {
+    let mut seen: HashMap<String, Vec<usize>> = HashMap::new();
+
+    for (index, directory) in config.directories.iter().enumerate() {
+        let key = match &directory.path_regex {
+            Some(path_regex) => format!("path_regex {}", path_regex),
+            None => match expand_path(&directory.path) {
+                Ok(path) => normalize_path(&path),
+                Err(_) => continue,
+            },
+        };
+        seen.entry(key).or_default().push(index);
+    }
+
+    let mut duplicates: Vec<(String, Vec<usize>)> = seen
+        .into_iter()
+        .filter(|(_, indices)| indices.len() > 1)
+        .collect();
+    duplicates.sort();
+    for (path, indices) in duplicates {
+        let problem = Problem::warning(format!(
+            "{}: configured by {} [[directory]] entries",
+            path,
+            indices.len()
+        ));
+        problems.push(locate_directory(sources, indices[1], problem));
+    }
+}
+
+/// Points a problem about the `index`th directory of the merged config at
+/// the file and line it's configured in
+fn locate_directory(sources: &[Source], mut index: usize, problem: Problem) -> Problem {
+    for source in sources {
+        if index < source.directories {
+            let path = ["directory".to_string(), index.to_string()];
+            return source.locate(problem, source.find(&path));
+        }
+        index -= source.directories;
+    }
+
+    problem
+}
+
+/// Checks that the .env files of literal directories exist and parse
+fn check_env_files(cache: &Cache, problems: &mut Vec<Problem>) {
+    let mut dirs: Vec<_> = cache.dirs().collect();
+    dirs.sort_by_key(|(path, _)| *path);
+
+    for (path, dir) in dirs {
+        for file in &dir.load_from {
+            let env_path = Path::new(path).join(&file.path);
+            match std::fs::read_to_string(&env_path) {
+                Err(_) if file.optional => {}
+                Err(err) => problems.push(Problem::warning(format!(
+                    "{}: could not read env file {}: {}",
+                    path,
+                    env_path.display(),
+                    err
+                ))),
+                Ok(_) if file.encrypted => {}
+                Ok(content) => match parse_env_file(&content, &env_path.to_string_lossy()) {
+                    Ok(vars) => check_names(
+                        &env_path.to_string_lossy(),
+                        &Shell::Bash,
+                        &vars,
+                        &[],
+                        &[],
+                        problems,
+                    ),
+                    Err(err) => problems.push(Problem::warning(err.to_string())),
+                },
+            }
+        }
+    }
+}
+
+/// Checks the content of the global config at `path` and the files it
+/// includes
+/// Returns the cache built from it so local configs of the configured
+/// directories can be found
+pub fn check_config(content: &str, path: &str) -> (Vec<Problem>, Option<Cache>) {
+    let mut problems = vec![];
+    let mut source = Source::new(None, content);
+    let Some(mut config) = parse::<Config>(&source, &mut problems) else {
+        return (problems, None);
+    };
+    source.directories = config.directories.len();
+
+    // Included files are parsed in the order their directories are merged in
+    let mut sources = vec![source];
+    let path = Path::new(path);
+    let merged = config.merge_includes(
+        path.parent().unwrap_or(Path::new("/")),
+        &mut vec![],
+        &mut vec![path.to_path_buf()],
+        &mut |path, content| {
+            let mut source = Source::new(Some(path.display().to_string()), content);
+            let included = parse::<Config>(&source, &mut problems).unwrap_or_default();
+            source.directories = included.directories.len();
+            sources.push(source);
+            Ok(included)
+        },
+    );
+    if let Err(err) = merged {
+        problems.push(Problem::error(format!("{:#}", err)));
+        return (problems, None);
+    }
+
+    check_duplicates(&config, &sources, &mut problems);
+
+    // Profiles are checked as if all of them were active
+    let mut profiles: Vec<String> = config.profiles.keys().cloned().collect();
+    profiles.sort();
+    if let Err(err) = config.apply_profiles(&profiles) {
+        problems.push(Problem::error(format!("{:#}", err)));
+    }
+
+    let cache = match Cache::from_config(&config, "") {
+        Ok(cache) => cache,
+        Err(err) => {
+            problems.push(Problem::error(format!("{:#}", err)));
+            return (problems, None);
+        }
+    };
+
+    let shell = Shell::from_string(&cache.shell).unwrap_or_else(|_| {
+        problems.push(Problem::error(format!("invalid shell \"{}\"", cache.shell)));
+        Shell::Bash
+    });
+
+    let mut dirs: Vec<_> = cache.dirs().collect();
+    dirs.sort_by_key(|(path, _)| *path);
+    for (path, dir) in dirs {
+        check_names(
+            path,
+            &shell,
+            &dir.variables,
+            &dir.aliases,
+            &sources,
+            &mut problems,
+        );
+    }
+    for (pattern, dir) in cache.patterns() {
+        check_names(
+            &pattern.to_string(),
+            &shell,
+            &dir.variables,
+            &dir.aliases,
+            &sources,
+            &mut problems,
+        );
+    }
+    check_env_files(&cache, &mut problems);
+
+    (problems, Some(cache))
+}
+
+/// Checks the content of a local config, aliases have to be valid for the
+/// shell from the global config
+pub fn check_local_config(content: &str, shell: &Shell) -> Vec<Problem> {
+    let mut problems = vec![];
+    let source = Source::new(None, content);
+
+    if let Some(config) = parse::<LocalConfig>(&source, &mut problems) {
+        check_names(
+            "local config",
+            shell,
+            config.variables.as_deref().unwrap_or(&[]),
+            config.aliases.as_deref().unwrap_or(&[]),
+            &[source],
+            &mut problems,
+        );
+
+        for entry in config.bin.iter().flatten() {
+            if let Err(err) = expand_path(entry) {
+                problems.push(Problem::error(format!("local config: {:#}", err)));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Checks the global config and the local configs of the current directory
+/// and every configured directory, printing any problems
+pub fn check(config_path: &str) -> Result<()> {
+    let content = std::fs::read_to_string(config_path)
+        .map_err(|err| anyhow!("Could not read config file at {}: {}", config_path, err))?;
+    let (problems, cache) = check_config(&content, config_path);
+    let mut reports = vec![(config_path.to_string(), problems)];
+    let shell = cache
+        .as_ref()
+        .and_then(|cache| Shell::from_string(&cache.shell).ok())
+        .unwrap_or(Shell::Bash);
+
+    let mut local_dirs: Vec<String> = cache
+        .iter()
+        .flat_map(|cache| cache.dirs().map(|(path, _)| path.clone()))
+        .collect();
+    if let Ok(current_dir) = std::env::current_dir() {
+        local_dirs.push(current_dir.to_string_lossy().to_string());
+    }
+    local_dirs.sort();
+    local_dirs.dedup();
+
+    for dir in local_dirs {
+        let local_path = format!("{}/{}", dir, "cdwe.toml");
+        if Path::new(&local_path) == Path::new(config_path) {
+            continue;
+        }
+        if let Ok(content) = std::fs::read_to_string(&local_path) {
+            reports.push((local_path, check_local_config(&content, &shell)));
+        }
+    }
+
+    let mut errors = 0;
+    let mut warnings = 0;
+    for (path, problems) in &reports {
+        for problem in problems {
+            match problem.level {
+                Level::Error => errors += 1,
+                Level::Warning => warnings += 1,
+            }
+            let file = problem.file.as_deref().unwrap_or(path);
+            match problem.line {
+                Some(line) => println!("{}:{}: {}", file, line, problem),
+                None => println!("{}: {}", file, problem),
+            }
+        }
+    }
+
+    let checked = reports.len();
+    match (errors, warnings) {
+        (0, 0) => println!("checked {} config files, no problems found", checked),
+        _ => println!(
+            "checked {} config files, found {} errors and {} warnings",
+            checked, errors, warnings
+        ),
+    }
+
+    if errors > 0 {
+        return Err(anyhow!("invalid config"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_config, check_local_config, Level, Problem};
+    use crate::cmd::Shell;
+
+    fn messages(problems: &[Problem], level: Level) -> Vec<&str> {
+        problems
+            .iter()
+            .filter(|p| p.level == level)
+            .map(|p| p.message.as_str())
+            .collect()
+    }
+
+    fn lines(problems: &[Problem], level: Level) -> Vec<Option<usize>> {
+        problems
+            .iter()
+            .filter(|p| p.level == level)
+            .map(|p| p.line)
+            .collect()
+    }
+
+    #[test]
+    fn test_check_config() {
+        let (problems, cache) = check_config(
+            r#"
+            [config]
+            shell = "bash"
+            env_hint = true
+
+            [[directory]]
+            path = "/cdwe_check/project"
+            vars = { "GOOD" = "1", "BAD-NAME" = "1" }
+            runs = ["git fetch -p"]
+            load_from = [".env", { path = ".env.local", optional = true }]
+
+            [[directory]]
+            path = "/cdwe_check/project/"
+            aliases = [{ name = "-x", commands = ["ls"] }]
+            "#,
+            "/cdwe_check/cdwe.toml",
+        );
+
+        assert!(cache.is_some());
+        assert_eq!(
+            messages(&problems, Level::Warning),
+            vec![
+                "unknown key config.env_hint",
+                "unknown key directory.0.runs",
+                "/cdwe_check/project: configured by 2 [[directory]] entries",
+                "/cdwe_check/project: could not read env file /cdwe_check/project/.env: No such file or directory (os error 2)",
+            ]
+        );
+        assert_eq!(
+            messages(&problems, Level::Error),
+            vec![
+                "/cdwe_check/project: invalid variable name \"BAD-NAME\"",
+                "/cdwe_check/project: invalid alias name \"-x\"",
+            ]
+        );
+        assert_eq!(
+            lines(&problems, Level::Warning),
+            vec![Some(4), Some(9), Some(12), None]
+        );
+        assert_eq!(lines(&problems, Level::Error), vec![Some(8), Some(14)]);
+        assert!(problems.iter().all(|p| p.file.is_none()));
+    }
+
+    #[test]
+    fn test_check_config_includes() {
+        let dir = std::env::temp_dir().join(format!("cdwe-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let included = dir.join("work.toml");
+        std::fs::write(
+            &included,
+            "[[directory]]\npath = \"/work\"\n\n[[directory]]\npath = \"/dev\"\nruns = []\n",
+        )
+        .unwrap();
+
+        let config_path = dir.join("config.toml");
+        let (problems, _) = check_config(
+            "include = [\"work.toml\"]\n\n[[directory]]\npath = \"/dev\"\n",
+            config_path.to_str().unwrap(),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let included = Some(included.to_str().unwrap().to_string());
+        assert_eq!(
+            problems,
+            vec![
+                Problem {
+                    file: included.clone(),
+                    line: Some(6),
+                    ..Problem::warning("unknown key directory.1.runs".to_string())
+                },
+                Problem {
+                    file: included,
+                    line: Some(4),
+                    ..Problem::warning("/dev: configured by 2 [[directory]] entries".to_string())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_config_errors() {
+        let (problems, cache) =
+            check_config("[[directory]]\npath = \"/dev\"\nvars = 1\n", "/cdwe.toml");
+        assert!(cache.is_none());
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].message.contains("line 3"));
+
+        let (problems, _) = check_config("[[directory]]\npath_regex = \"(\"\n", "/cdwe.toml");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0]
+            .message
+            .starts_with("Invalid regex in directory path_regex: ("));
+
+        assert_eq!(
+            check_local_config(
+                "[[variables]]\nname = \"A B\"\nvalue = \"1\"\nextra = 1\n",
+                &Shell::Bash
+            ),
+            vec![
+                Problem {
+                    line: Some(4),
+                    ..Problem::warning("unknown key variables.0.extra".to_string())
+                },
+                Problem {
+                    line: Some(2),
+                    ..Problem::error("local config: invalid variable name \"A B\"".to_string())
+                },
+            ]
+        );
+
+        let bin = "[[directory]]\npath = \"/dev\"\nbin = [\"$CDWE_CHECK_UNSET/bin\"]\n";
+        let (problems, cache) = check_config(bin, "/cdwe.toml");
+        assert!(cache.is_none());
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0]
+            .message
+            .starts_with("Could not expand path \"$CDWE_CHECK_UNSET/bin\""));
+        assert_eq!(
+            check_local_config("bin = [\"$CDWE_CHECK_UNSET/bin\"]\n", &Shell::Bash).len(),
+            1
+        );
+
+        let aliases = "[[aliases]]\nname = \"my-build\"\ncommands = [\"make\"]\n";
+        assert!(check_local_config(aliases, &Shell::Bash).is_empty());
+        assert_eq!(
+            check_local_config(aliases, &Shell::Posix),
+            vec![Problem {
+                line: Some(2),
+                ..Problem::error("local config: invalid alias name \"my-build\"".to_string())
+            }]
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use crate::cmd::shell::Shell;
-use clap::{command, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 
 #[derive(Debug, Parser)]
 pub struct Cli {
@@ -29,4 +29,45 @@ pub enum Commands {
         #[arg(value_name = "SHELL", required = true)]
         shell: Option<Shell>,
     },
+    /// Switches between the `[profile.<name>]` sections of the config
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+    /// Moves the config, caches and shell scripts out of $HOME into the XDG
+    /// base directories
+    Migrate,
+    /// Validates the config and the local configs of configured directories
+    Check,
+    /// Encrypts an .env file with the local key, writing FILE.enc by default
+    Encrypt {
+        #[arg(value_name = "FILE")]
+        file: String,
+        #[arg(long, short)]
+        output: Option<String>,
+        /// Public key of someone else who should be able to decrypt the file
+        #[arg(long = "recipient", short)]
+        recipients: Vec<String>,
+    },
+    /// Decrypts an encrypted .env file with the local key
+    Decrypt {
+        #[arg(value_name = "FILE")]
+        file: String,
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfileCommands {
+    /// Activates profiles, several are separated by commas
+    #[command(arg_required_else_help = true)]
+    Use {
+        #[arg(value_name = "NAME")]
+        names: String,
+    },
+    /// Deactivates every profile
+    Clear,
+    /// Lists the profiles of the config, marking the active ones
+    List,
 }
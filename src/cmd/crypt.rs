@@ -0,0 +1,77 @@
+use crate::crypt;
+use age::x25519::Recipient;
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+
+/// Encrypts an .env file to the local key and any extra recipients
+/// The local key is generated on first use
+pub fn encrypt_file(file: &str, output: Option<String>, recipients: &[String]) -> Result<()> {
+    let key_path = crypt::get_key_path()?;
+    let key = if key_path.exists() {
+        crypt::load_key(&key_path)?
+    } else {
+        let key = crypt::generate_key(&key_path)?;
+        eprintln!(
+            "Generated a new key at {}, public key: {}",
+            key_path.display(),
+            key.to_public()
+        );
+        key
+    };
+
+    let mut all_recipients = vec![key.to_public()];
+    for recipient in recipients {
+        all_recipients.push(
+            recipient
+                .parse::<Recipient>()
+                .map_err(|err| anyhow!("Invalid recipient {}: {}", recipient, err))?,
+        );
+    }
+
+    let content =
+        std::fs::read(file).with_context(|| format!("Could not read file at {}", file))?;
+    let encrypted = crypt::encrypt(&content, &all_recipients)
+        .with_context(|| format!("Could not encrypt {}", file))?;
+
+    let output = output.unwrap_or_else(|| format!("{}.enc", file));
+    std::fs::write(&output, encrypted)
+        .with_context(|| format!("Could not write file at {}", output))?;
+
+    Ok(())
+}
+
+/// Decrypts an encrypted .env file with the local key, printing it unless an
+/// output file is given
+pub fn decrypt_file(file: &str, output: Option<String>) -> Result<()> {
+    let key = crypt::load_key(&crypt::get_key_path()?)?;
+    let content =
+        std::fs::read(file).with_context(|| format!("Could not read file at {}", file))?;
+    let decrypted =
+        crypt::decrypt(&content, &key).with_context(|| format!("Could not decrypt {}", file))?;
+
+    match output {
+        Some(output) => write_private(&output, &decrypted)
+            .with_context(|| format!("Could not write file at {}", output))?,
+        None => print!("{}", String::from_utf8_lossy(&decrypted)),
+    }
+
+    Ok(())
+}
+
+/// Writes decrypted content so only the owner can read it, an existing file
+/// is restricted before anything is written to it
+fn write_private(path: &str, content: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(content)
+}
@@ -0,0 +1,343 @@
+use super::Shell;
+use crate::config::EnvAlias;
+use anyhow::{anyhow, Result};
+
+/// Environment variable names every supported shell accepts
+pub fn is_valid_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Alias names are emitted as function names, so they are kept to characters
+/// that can't be interpreted by the shell
+/// POSIX sh only allows the same names as for variables, dash refuses to
+/// define any other function
+pub fn is_valid_alias_name(shell: &Shell, name: &str) -> bool {
+    if let Shell::Posix = shell {
+        return is_valid_var_name(name);
+    }
+
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Builds the script `run` prints for the shell to evaluate
+/// Nothing is printed until the whole script is built, so an error never
+/// leaves the shell evaluating half a script
+pub struct Emitter {
+    shell: Shell,
+    lines: Vec<String>,
+}
+
+impl Emitter {
+    pub fn new(shell: Shell) -> Self {
+        Emitter {
+            shell,
+            lines: vec![],
+        }
+    }
+
+    /// Adds a line to the script as is
+    pub fn push(&mut self, line: impl Into<String>) {
+        self.lines.push(line.into());
+    }
+
+    pub fn export(&mut self, name: &str, value: &str) -> Result<()> {
+        check_var_name(name)?;
+        let value = self.shell.quote(value);
+        let line = match self.shell {
+            Shell::Bash | Shell::Zsh | Shell::Posix => format!("export {}={}", name, value),
+            Shell::Fish => format!("set -gx {} {}", name, value),
+            Shell::Nu => format!("load-env {{ {}: {} }}", name, value),
+            Shell::Pwsh => format!("$env:{} = {}", name, value),
+        };
+        self.push(line);
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &str) -> Result<()> {
+        check_var_name(name)?;
+        let line = match self.shell {
+            Shell::Bash | Shell::Zsh | Shell::Posix => format!("unset {}", name),
+            Shell::Fish => format!("set -e {}", name),
+            Shell::Nu => format!("hide-env -i {}", name),
+            Shell::Pwsh => format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", name),
+        };
+        self.push(line);
+        Ok(())
+    }
+
+    /// Defines an alias as a shell function running its commands in order
+    pub fn alias(&mut self, alias: &EnvAlias) -> Result<()> {
+        check_alias_name(&self.shell, &alias.name)?;
+        let header = match (&self.shell, &alias.description) {
+            (Shell::Bash | Shell::Zsh | Shell::Posix, _) => format!("{}() {{", alias.name),
+            (Shell::Fish, Some(description)) => format!(
+                "function {} -d {}",
+                alias.name,
+                self.shell.quote(description)
+            ),
+            (Shell::Fish, None) => format!("function {}", alias.name),
+            (Shell::Nu, _) => format!("def --env {} [] {{", alias.name),
+            (Shell::Pwsh, _) => format!("function global:{} {{", alias.name),
+        };
+        let footer = match self.shell {
+            Shell::Bash | Shell::Zsh | Shell::Posix | Shell::Nu | Shell::Pwsh => "}",
+            Shell::Fish => "end",
+        };
+
+        // Nushell uses the comment right above a definition as its description
+        if let (Shell::Nu, Some(description)) = (&self.shell, &alias.description) {
+            for line in description.lines() {
+                self.push(format!("# {}", line));
+            }
+        }
+        self.push(header);
+        // Powershell shows the synopsis of comment based help in Get-Help
+        if let (Shell::Pwsh, Some(description)) = (&self.shell, &alias.description) {
+            self.push("<#");
+            self.push(".SYNOPSIS");
+            self.push(description.replace("#>", "# >"));
+            self.push("#>");
+        }
+        for command in &alias.commands {
+            self.push(command.as_str());
+        }
+        self.push(footer);
+        Ok(())
+    }
+
+    pub fn unalias(&mut self, name: &str) -> Result<()> {
+        check_alias_name(&self.shell, name)?;
+        let line = match self.shell {
+            Shell::Bash | Shell::Zsh => format!("unset -f {} &> /dev/null", name),
+            Shell::Posix => format!("unset -f {} 2> /dev/null", name),
+            Shell::Fish => format!("functions -e {}", name),
+            Shell::Nu => format!("hide {}", name),
+            Shell::Pwsh => format!(
+                "Remove-Item Function:{} -ErrorAction SilentlyContinue",
+                name
+            ),
+        };
+        self.push(line);
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        for line in &self.lines {
+            println!("{}", line);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+}
+
+fn check_var_name(name: &str) -> Result<()> {
+    if is_valid_var_name(name) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Invalid variable name \"{}\", names may only contain letters, digits and underscores and can't start with a digit",
+            name
+        ))
+    }
+}
+
+fn check_alias_name(shell: &Shell, name: &str) -> Result<()> {
+    match shell {
+        _ if is_valid_alias_name(shell, name) => Ok(()),
+        Shell::Posix => Err(anyhow!(
+            "Invalid alias name \"{}\", names may only contain letters, digits and underscores and can't start with a digit",
+            name
+        )),
+        _ => Err(anyhow!(
+            "Invalid alias name \"{}\", names may only contain letters, digits, '_', '-' and '.'",
+            name
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_var_name, Emitter};
+    use crate::cmd::Shell;
+    use crate::config::EnvAlias;
+
+    #[test]
+    fn test_is_valid_var_name() {
+        assert!(is_valid_var_name("NODE_ENV"));
+        assert!(is_valid_var_name("_private1"));
+        assert!(!is_valid_var_name(""));
+        assert!(!is_valid_var_name("1VAR"));
+        assert!(!is_valid_var_name("MY-VAR"));
+        assert!(!is_valid_var_name("A;rm -rf ~"));
+    }
+
+    #[test]
+    fn test_export_rejects_invalid_names() {
+        let mut emitter = Emitter::new(Shell::Bash);
+        assert!(emitter.export("$(whoami)", "value").is_err());
+        assert!(emitter.unset("A B").is_err());
+        assert!(emitter.lines.is_empty());
+    }
+
+    #[test]
+    fn test_fish_output() {
+        let mut emitter = Emitter::new(Shell::Fish);
+        emitter.export("NAME", "it's").unwrap();
+        emitter.unset("OLD").unwrap();
+        emitter
+            .alias(&EnvAlias {
+                name: "build".to_string(),
+                commands: vec!["cargo build".to_string()],
+                description: Some("Build it".to_string()),
+            })
+            .unwrap();
+        emitter.unalias("test").unwrap();
+
+        assert_eq!(
+            emitter.lines,
+            vec![
+                "set -gx NAME 'it\\'s'",
+                "set -e OLD",
+                "function build -d 'Build it'",
+                "cargo build",
+                "end",
+                "functions -e test",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bash_output() {
+        let mut emitter = Emitter::new(Shell::Bash);
+        emitter.export("NAME", "it's").unwrap();
+        emitter.unset("OLD").unwrap();
+        emitter
+            .alias(&EnvAlias {
+                name: "build".to_string(),
+                commands: vec!["cargo build".to_string()],
+                description: Some("Build it".to_string()),
+            })
+            .unwrap();
+        emitter.unalias("test").unwrap();
+        assert!(emitter.unalias("a;b").is_err());
+
+        assert_eq!(
+            emitter.lines,
+            vec![
+                "export NAME='it'\\''s'",
+                "unset OLD",
+                "build() {",
+                "cargo build",
+                "}",
+                "unset -f test &> /dev/null",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_posix_output() {
+        let mut emitter = Emitter::new(Shell::Posix);
+        emitter.export("NAME", "it's").unwrap();
+        emitter.unset("OLD").unwrap();
+        emitter
+            .alias(&EnvAlias {
+                name: "build".to_string(),
+                commands: vec!["make".to_string()],
+                description: Some("Build it".to_string()),
+            })
+            .unwrap();
+        emitter.unalias("test").unwrap();
+        assert!(emitter
+            .alias(&EnvAlias {
+                name: "my-build".to_string(),
+                commands: vec!["make".to_string()],
+                description: None,
+            })
+            .is_err());
+        assert!(emitter.unalias("my.test").is_err());
+
+        assert_eq!(
+            emitter.lines,
+            vec![
+                "export NAME='it'\\''s'",
+                "unset OLD",
+                "build() {",
+                "make",
+                "}",
+                "unset -f test 2> /dev/null",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nu_output() {
+        let mut emitter = Emitter::new(Shell::Nu);
+        emitter.export("NAME", "it's").unwrap();
+        emitter.unset("OLD").unwrap();
+        emitter
+            .alias(&EnvAlias {
+                name: "build".to_string(),
+                commands: vec!["cargo build".to_string()],
+                description: Some("Build it".to_string()),
+            })
+            .unwrap();
+        emitter.unalias("test").unwrap();
+
+        assert_eq!(
+            emitter.lines,
+            vec![
+                "load-env { NAME: r#'it's'# }",
+                "hide-env -i OLD",
+                "# Build it",
+                "def --env build [] {",
+                "cargo build",
+                "}",
+                "hide test",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pwsh_output() {
+        let mut emitter = Emitter::new(Shell::Pwsh);
+        emitter.export("NAME", "it's").unwrap();
+        emitter.unset("OLD").unwrap();
+        emitter
+            .alias(&EnvAlias {
+                name: "build".to_string(),
+                commands: vec!["cargo build".to_string()],
+                description: Some("Build it".to_string()),
+            })
+            .unwrap();
+        emitter.unalias("test").unwrap();
+
+        assert_eq!(
+            emitter.lines,
+            vec![
+                "$env:NAME = 'it''s'",
+                "Remove-Item Env:OLD -ErrorAction SilentlyContinue",
+                "function global:build {",
+                "<#",
+                ".SYNOPSIS",
+                "Build it",
+                "#>",
+                "cargo build",
+                "}",
+                "Remove-Item Function:test -ErrorAction SilentlyContinue",
+            ]
+        );
+    }
+}
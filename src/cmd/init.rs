@@ -1,15 +1,21 @@
 use super::super::config::Config;
 use super::Shell;
+use crate::paths;
 use anyhow::{Context, Result};
+use clap::ValueEnum;
 use std::path::Path;
 
+fn create_parent_dir(path: &str) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+
+    Ok(())
+}
+
 pub fn init_shell(config: Option<Config>, shell: Shell) -> Result<()> {
-    let home_var = std::env::var("HOME").context("no $HOME set")?;
-    let home = Path::new(&home_var);
-    let toml_path = std::path::Path::join(home, "cdwe.toml")
-        .to_str()
-        .context("failed to get toml path")?
-        .to_string();
+    let toml_path = paths::config_path()?;
 
     let config_path = shell.get_config_path()?;
     let mut shell_script = shell.get_shell_script();
@@ -30,21 +36,24 @@ pub fn init_shell(config: Option<Config>, shell: Shell) -> Result<()> {
     };
 
     shell_script = shell_script.replace("{{{cd_command}}}", &cd_command);
+    shell_script = shell_script.replace(
+        "{{{output_path}}}",
+        &format!("{}.out", &shell_script_target),
+    );
 
+    create_parent_dir(&shell_script_target)?;
     std::fs::write(&shell_script_target, shell_script)?;
 
     let toml_content: String = std::fs::read_to_string(&toml_path).unwrap_or("".to_string());
 
     if toml_content.is_empty() {
-        let default_config = Config::default_for_shell(shell);
+        let default_config = Config::default_for_shell(shell.clone());
+        create_parent_dir(&toml_path)?;
         std::fs::write(&toml_path, toml::to_string(&default_config)?)
             .context("failed to write default config")?;
     }
 
-    let source_string = format!(
-        "if [ -f '{}' ]; then . '{}'; fi",
-        &shell_script_target, &shell_script_target
-    );
+    let source_string = shell.get_source_string(&shell_script_target);
 
     let mut config = std::fs::read_to_string(&config_path)
         .with_context(|| format!("failed to read config path {}", config_path))?;
@@ -56,13 +65,11 @@ pub fn init_shell(config: Option<Config>, shell: Shell) -> Result<()> {
     Ok(())
 }
 
-pub fn remove_shell(shell: Shell) -> Result<()> {
-    let shell_script_target = shell.get_shell_script_target()?;
+/// Removes the line sourcing `shell_script_target` from the shell config
+/// along with the script itself
+fn uninstall_script(shell: &Shell, shell_script_target: &str) -> Result<()> {
     let config_path = shell.get_config_path()?;
-    let source_string = format!(
-        "if [ -f '{}' ]; then . '{}'; fi",
-        &shell_script_target, &shell_script_target
-    );
+    let source_string = shell.get_source_string(shell_script_target);
     let mut config = std::fs::read_to_string(&config_path)
         .with_context(|| format!("failed to read config path {}", config_path))?;
 
@@ -72,8 +79,76 @@ pub fn remove_shell(shell: Shell) -> Result<()> {
             .with_context(|| format!("Failed to write to {}", &config_path))?;
     }
 
-    std::fs::remove_file(&shell_script_target)
-        .with_context(|| format!("failed to remove config file {}", &shell_script_target))?;
+    std::fs::remove_file(shell_script_target)
+        .with_context(|| format!("failed to remove config file {}", shell_script_target))?;
+
+    // Only written by shells that can't evaluate the output of run directly
+    let _ = std::fs::remove_file(format!("{}.out", shell_script_target));
+
+    Ok(())
+}
+
+pub fn remove_shell(shell: Shell) -> Result<()> {
+    // Installs that were never migrated still have the script in $HOME
+    let legacy_target = shell.get_legacy_shell_script_target()?;
+    let legacy_installed = Path::new(&legacy_target).exists();
+    if legacy_installed {
+        uninstall_script(&shell, &legacy_target)?;
+    }
+
+    let shell_script_target = shell.get_shell_script_target()?;
+    if legacy_installed && !Path::new(&shell_script_target).exists() {
+        return Ok(());
+    }
+
+    uninstall_script(&shell, &shell_script_target)
+}
+
+/// Moves files from the locations used before XDG support
+/// The legacy config is moved to the XDG config dir, legacy caches are removed
+/// since they are rebuilt on demand, and shells using a script installed in
+/// $HOME are set up again with the script in the XDG data dir
+pub fn migrate() -> Result<()> {
+    let legacy_config = paths::legacy_config_path()?;
+    let xdg_config = paths::xdg_config_path()?;
+
+    if Path::new(&legacy_config).exists() {
+        if Path::new(&xdg_config).exists() {
+            eprintln!(
+                "WARNING: both {} and {} exist, keeping both, {} is used",
+                legacy_config, xdg_config, xdg_config
+            );
+        } else {
+            create_parent_dir(&xdg_config)?;
+            std::fs::copy(&legacy_config, &xdg_config)
+                .with_context(|| format!("failed to copy config to {}", xdg_config))?;
+            std::fs::remove_file(&legacy_config)
+                .with_context(|| format!("failed to remove {}", legacy_config))?;
+            println!("Moved {} to {}", legacy_config, xdg_config);
+        }
+    }
+
+    for cache_path in paths::legacy_cache_paths()? {
+        if std::fs::remove_file(&cache_path).is_ok() {
+            println!("Removed {}", cache_path);
+        }
+    }
+
+    for shell in Shell::value_variants() {
+        let legacy_target = shell.get_legacy_shell_script_target()?;
+        if !Path::new(&legacy_target).exists() {
+            continue;
+        }
+
+        uninstall_script(shell, &legacy_target)?;
+        let config = Config::from_config_file(&paths::config_path()?).ok();
+        init_shell(config, shell.clone())?;
+        println!(
+            "Moved {} to {}, restart your shell to load it",
+            legacy_target,
+            shell.get_shell_script_target()?
+        );
+    }
 
     Ok(())
 }
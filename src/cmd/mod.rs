@@ -1,9 +1,18 @@
+mod check;
+#[allow(clippy::module_inception)]
 mod cmd;
+mod crypt;
+mod emit;
 mod init;
+mod profile;
 mod run;
 mod shell;
 
-pub use cmd::{Cli, Commands};
-pub use init::{init_shell, remove_shell};
-pub use run::{run, run_local};
+pub use check::check;
+pub use cmd::{Cli, Commands, ProfileCommands};
+pub use crypt::{decrypt_file, encrypt_file};
+pub use emit::{is_valid_var_name, Emitter};
+pub use init::{init_shell, migrate, remove_shell};
+pub use profile::{get_active_profiles, list_profiles, use_profiles};
+pub use run::{leave_local, run};
 pub use shell::Shell;
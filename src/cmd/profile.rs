@@ -0,0 +1,93 @@
+use crate::config::Config;
+use crate::paths;
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+
+fn parse_profiles(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Names of the active profiles, from `$CDWE_PROFILE` if it's set and from
+/// the profiles selected with `cdwe profile use` otherwise
+/// Several profiles are separated by commas, later ones overlay earlier ones
+pub fn get_active_profiles() -> Result<Vec<String>> {
+    if let Ok(value) = std::env::var(paths::PROFILE_VAR) {
+        return Ok(parse_profiles(&value));
+    }
+
+    match std::fs::read_to_string(paths::profile_path()?) {
+        Ok(content) => Ok(parse_profiles(&content)),
+        Err(_) => Ok(vec![]),
+    }
+}
+
+fn load_config(config_path: &str) -> Result<Config> {
+    let content = std::fs::read_to_string(config_path)
+        .with_context(|| format!("Could not read config file at {}", config_path))?;
+    let (config, _) = Config::from_str_with_includes(&content, config_path)?;
+
+    Ok(config)
+}
+
+/// Selects the profiles used from now on, an empty list goes back to no
+/// profile
+pub fn use_profiles(config_path: &str, names: &str) -> Result<()> {
+    let config = load_config(config_path)?;
+    let names = parse_profiles(names);
+    if let Some(unknown) = names.iter().find(|n| !config.profiles.contains_key(*n)) {
+        return Err(anyhow!("unknown profile {}", unknown));
+    }
+
+    let profile_path = paths::profile_path()?;
+    if names.is_empty() {
+        let _ = std::fs::remove_file(&profile_path);
+    } else {
+        if let Some(parent) = Path::new(&profile_path).parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {}", parent.display()))?;
+        }
+        std::fs::write(&profile_path, names.join(","))
+            .with_context(|| format!("Could not write {}", profile_path))?;
+    }
+
+    if std::env::var(paths::PROFILE_VAR).is_ok() {
+        eprintln!(
+            "WARNING: ${} is set and overrides the selected profiles",
+            paths::PROFILE_VAR
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the configured profiles, marking the active ones
+pub fn list_profiles(config_path: &str) -> Result<()> {
+    let config = load_config(config_path)?;
+    let active = get_active_profiles()?;
+
+    let mut names: Vec<&String> = config.profiles.keys().collect();
+    names.sort();
+    for name in names {
+        let marker = if active.contains(name) { "*" } else { " " };
+        println!("{} {}", marker, name);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_profiles;
+
+    #[test]
+    fn test_parse_profiles() {
+        assert_eq!(parse_profiles("work"), vec!["work"]);
+        assert_eq!(parse_profiles(" work, oncall ,\n"), vec!["work", "oncall"]);
+        assert!(parse_profiles("").is_empty());
+    }
+}
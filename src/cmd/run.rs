@@ -1,240 +1,533 @@
-use super::Shell;
+use super::Emitter;
 use crate::cache::{Cache, DirCache};
-use crate::config::{EnvAlias, EnvVariable, LocalConfig};
-use crate::utils::trim_quotes;
-use anyhow::{anyhow, Result};
+use crate::config::{EnvAlias, EnvVariable, LoadFromEntry, LocalConfig};
+use crate::crypt;
+use crate::env_file::parse_env_file;
+use crate::list::{apply_list, bin_variable, is_list, merge_variable};
+use crate::stash::Stash;
+use crate::utils::interpolate_variables;
+use crate::value::{current_values, resolve_values, CommandCache};
+use anyhow::Result;
+use std::io::ErrorKind;
 use std::path::Path;
 
-/// Parses the content of an .env file with the following structure
-/// ```
-/// SOME_VAR=test
-/// ANOTHER_VAR="test"
-/// ````
-///
-/// Supports values with or without quotes
-fn parse_env_file(content: &str, file_name: &str) -> Result<Vec<EnvVariable>> {
-    let lines = content
-        .lines()
-        .filter(|line| !line.contains('#') && !line.trim().is_empty());
-
-    let mut vars = vec![];
-
-    for (index, line) in lines.enumerate() {
-        let split = line
-            .split_once('=')
-            .ok_or_else(|| anyhow!("Invalid line in file: {}:{}: {}", file_name, index, line))?;
-
-        let key = trim_quotes(split.0);
-        let value = trim_quotes(split.1);
-
-        vars.push(EnvVariable {
-            name: key.to_string(),
-            value: value.to_string(),
-        });
-    }
-
-    Ok(vars)
+/// Decrypts the content of an encrypted .env file with the local key
+fn decrypt_env_file(content: &[u8]) -> Result<String> {
+    let key = crypt::load_key(&crypt::get_key_path()?)?;
+    Ok(String::from_utf8(crypt::decrypt(content, &key)?)?)
 }
 
-fn get_vars_from_env_file(base_path: &str, file_path: &str) -> Option<Vec<EnvVariable>> {
-    let env_path = Path::new(&base_path).join(file_path);
-    if let Ok(content) = std::fs::read_to_string(&env_path) {
-        match parse_env_file(&content, &env_path.to_string_lossy()) {
-            Ok(vars) => Some(vars),
-            Err(_) => None,
+/// Loads the variables of an .env file relative to `base_path`
+/// Problems are reported as warnings on stderr when `report_errors` is set,
+/// they never stop the rest of the environment from being applied
+fn get_vars_from_env_file(
+    base_path: &str,
+    file: &LoadFromEntry,
+    report_errors: bool,
+) -> Option<Vec<EnvVariable>> {
+    let env_path = Path::new(&base_path).join(&file.path);
+    let content = match std::fs::read(&env_path) {
+        Ok(content) => content,
+        Err(err) => {
+            if report_errors && !(file.optional && err.kind() == ErrorKind::NotFound) {
+                eprintln!(
+                    "WARNING: could not read env file {}: {}",
+                    env_path.display(),
+                    err
+                );
+            }
+            return None;
         }
+    };
+
+    let content = if file.encrypted {
+        decrypt_env_file(&content)
     } else {
-        None
+        String::from_utf8(content).map_err(|err| err.into())
+    };
+    let content = match content {
+        Ok(content) => content,
+        Err(err) => {
+            if report_errors {
+                eprintln!(
+                    "WARNING: could not read env file {}: {:#}",
+                    env_path.display(),
+                    err
+                );
+            }
+            return None;
+        }
+    };
+
+    match parse_env_file(&content, &env_path.to_string_lossy()) {
+        Ok(vars) => Some(vars),
+        Err(err) => {
+            if report_errors {
+                eprintln!("WARNING: {}", err);
+            }
+            None
+        }
     }
 }
 
-/// Given a cache unsets the environment variables for the old directory
+/// Collects the environment variables for a directory
 /// variables are taken from the dir and from any .env files specified in the config
-pub fn unset_variables(
-    variables: &Vec<EnvVariable>,
-    load_from: Option<&Vec<String>>,
+/// When a variable is defined more than once the last definition wins, or is
+/// combined with the earlier one for list variables
+pub fn get_variables(
+    variables: &[EnvVariable],
+    load_from: Option<&[LoadFromEntry]>,
     path: Option<&str>,
-) {
-    for var in variables.iter() {
-        println!("unset {}", var.name);
-    }
+    report_errors: bool,
+) -> Vec<EnvVariable> {
+    let mut resolved: Vec<EnvVariable> = vec![];
+    let mut push = |var: EnvVariable| merge_variable(&mut resolved, var);
 
-    // Unload variables from .env files specified in config
-    // for the old directory
+    for var in variables {
+        push(var.clone());
+    }
 
     if let (Some(path), Some(load_from)) = (path, load_from) {
+        // Load variables from .env files specified in config
         for file in load_from {
-            let vars = get_vars_from_env_file(path, file);
-            if let Some(vars) = vars {
-                for var in vars {
-                    println!("unset {}", var.name);
-                }
+            let vars = get_vars_from_env_file(path, file, report_errors);
+            for var in vars.into_iter().flatten() {
+                push(var);
             }
         }
     }
+
+    resolved
 }
 
-/// Given a cache sets the environment variables for the new directory
-/// variables are taken from the dir and from any .enf files specified in the config
-pub fn set_variables(
-    variables: &Vec<EnvVariable>,
-    load_from: Option<&Vec<String>>,
-    path: Option<&str>,
-) {
-    for var in variables {
-        println!("export {}=\"{}\"", var.name, var.value);
+/// Compares the variables of the old and new directory
+/// Returns the variables that have to be unset, and the variables that are
+/// either new or have a different value and have to be exported
+pub fn diff_variables(
+    old: &[EnvVariable],
+    new: &[EnvVariable],
+) -> (Vec<EnvVariable>, Vec<EnvVariable>) {
+    let removed = old
+        .iter()
+        .filter(|o| !new.iter().any(|n| n.name == o.name))
+        .cloned()
+        .collect();
+    let changed = new.iter().filter(|n| !old.contains(n)).cloned().collect();
+
+    (removed, changed)
+}
+
+/// Compares the aliases of the old and new directory
+/// Returns the aliases that have to be unset, and the aliases that are
+/// either new or have different commands and have to be (re)defined
+pub fn diff_aliases(old: &[EnvAlias], new: &[EnvAlias]) -> (Vec<EnvAlias>, Vec<EnvAlias>) {
+    let removed = old
+        .iter()
+        .filter(|o| !new.iter().any(|n| n.name == o.name))
+        .cloned()
+        .collect();
+    let changed = new.iter().filter(|n| !old.contains(n)).cloned().collect();
+
+    (removed, changed)
+}
+
+/// Unsets variables, restoring any value they had before cdwe set them
+/// For list variables only what cdwe changed is undone, so changes made to
+/// them in the meantime are kept
+pub fn unset_variables(
+    variables: &[EnvVariable],
+    stash: &mut Stash,
+    emitter: &mut Emitter,
+) -> Result<()> {
+    for var in variables.iter() {
+        let value = match stash.take_list(&var.name) {
+            Some(change) => change.undo(std::env::var(&var.name).ok().as_deref()),
+            None => stash.take(&var.name),
+        };
+        match value {
+            Some(value) => emitter.export(&var.name, &value)?,
+            None => emitter.unset(&var.name)?,
+        }
     }
 
-    if let (Some(path), Some(load_from)) = (path, load_from) {
-        // Load variables from .env files specified in config
-        for file in load_from {
-            let vars = get_vars_from_env_file(path, &file);
-            if let Some(vars) = vars {
-                for var in vars {
-                    println!("export {}=\"{}\"", var.name, var.value);
-                }
+    Ok(())
+}
+
+/// Exports variables, stashing the current value of any variable cdwe
+/// hasn't set yet
+/// List variables are applied to the current value, after undoing what cdwe
+/// changed in it before, so their entries never pile up
+pub fn set_variables(
+    variables: &[EnvVariable],
+    stash: &mut Stash,
+    emitter: &mut Emitter,
+) -> Result<()> {
+    for var in variables {
+        let list_change = stash.take_list(&var.name);
+        let undone =
+            list_change.map(|change| change.undo(std::env::var(&var.name).ok().as_deref()));
+
+        if is_list(var) {
+            // A variable cdwe replaced before is modified from its own value
+            let base = undone.unwrap_or_else(|| stash.original(&var.name));
+            stash.take(&var.name);
+            let (value, change) = apply_list(var, base.as_deref())?;
+            emitter.export(&var.name, &value)?;
+            stash.save_list(&var.name, change);
+        } else {
+            emitter.export(&var.name, &var.value)?;
+            match undone {
+                Some(value) => stash.save_value(&var.name, value),
+                None => stash.save(&var.name),
             }
         }
     }
+
+    Ok(())
 }
 
-pub fn set_aliases(aliases: &Vec<EnvAlias>, shell: &str) -> Result<()> {
-    let (start_str, end_str) = Shell::from_string(shell)?.get_alias_command();
+pub fn set_aliases(aliases: &[EnvAlias], emitter: &mut Emitter) -> Result<()> {
     for alias in aliases.iter() {
-        let mut alias_string = start_str.clone().replace("{{{alias_name}}}", &alias.name);
-        for cmd in &alias.commands {
-            alias_string.push_str(&format!("{}\n", cmd));
-        }
-
-        println!("{}\n{}\n", &alias_string, &end_str);
+        emitter.alias(alias)?;
     }
 
     Ok(())
 }
 
-pub fn unset_aliases(aliases: &Vec<EnvAlias>) {
+pub fn unset_aliases(aliases: &[EnvAlias], emitter: &mut Emitter) -> Result<()> {
     for alias in aliases.iter() {
-        println!("unset -f {} &> /dev/null", alias.name);
+        emitter.unalias(&alias.name)?;
     }
+
+    Ok(())
 }
 
-pub fn run_commands(commands: &Vec<String>) {
+pub fn run_commands(commands: &[String], emitter: &mut Emitter) {
     for command in commands.iter() {
-        println!("{}", command);
+        emitter.push(command.as_str());
     }
 }
 
-pub fn run(cache: &Cache, old_path: String, new_path: String) -> Result<()> {
-    let old_dir: Option<&DirCache> = cache.get(&old_path);
-    let new_dir: Option<&DirCache> = cache.get(&new_path);
+/// Returns the on leave commands of every entry that applies to `old_path`
+/// but not to `new_path`, innermost scope first
+fn get_on_leave_commands(cache: &Cache, old_path: &str, new_path: &str) -> Vec<String> {
+    let new_ancestors: Vec<&Path> = Path::new(new_path).ancestors().collect();
+
+    cache
+        .get_chain(old_path)
+        .into_iter()
+        .rev()
+        .filter(|(dir_path, _)| !new_ancestors.contains(&Path::new(dir_path)))
+        .flat_map(|(_, dir)| dir.on_leave.iter().cloned())
+        .collect()
+}
 
-    if old_dir.is_none() && new_dir.is_none() {
-        return Ok(());
-    }
+/// Interpolates the old and new variables
+/// Only the names of the old variables matter once they are left, so if they
+/// can't be interpolated anymore they are compared as they are
+/// New variables that can't be interpolated are left out with a warning
+/// References to the environment resolve to the values from before cdwe set
+/// them, so `PATH=$PATH:/bin` doesn't grow with every move
+fn interpolate(
+    old_vars: Vec<EnvVariable>,
+    new_vars: Vec<EnvVariable>,
+    stash: &Stash,
+) -> (Vec<EnvVariable>, Vec<EnvVariable>) {
+    let inherited = |name: &str| stash.original(name);
+    let old_vars = interpolate_variables(&old_vars, &inherited)
+        .into_iter()
+        .zip(&old_vars)
+        .map(|(interpolated, var)| interpolated.unwrap_or_else(|_| var.clone()))
+        .collect();
+    let new_vars = interpolate_variables(&new_vars, &inherited)
+        .into_iter()
+        .filter_map(|interpolated| match interpolated {
+            Ok(var) => Some(var),
+            Err(err) => {
+                eprintln!("WARNING: {:#}", err);
+                None
+            }
+        })
+        .collect();
 
-    // Unset old environment variables
-    if let Some(old_dir) = old_dir {
-        unset_variables(
-            &old_dir.variables,
-            Some(&old_dir.load_from),
-            Some(&old_path),
-        );
-        unset_aliases(&old_dir.aliases);
+    (old_vars, new_vars)
+}
+
+/// Emits only what changes between the old and the new environment
+fn transition(
+    (old_vars, old_aliases): (&[EnvVariable], &[EnvAlias]),
+    (new_vars, new_aliases): (&[EnvVariable], &[EnvAlias]),
+    stash: &mut Stash,
+    emitter: &mut Emitter,
+) -> Result<()> {
+    let (removed_vars, changed_vars) = diff_variables(old_vars, new_vars);
+    let (removed_aliases, changed_aliases) = diff_aliases(old_aliases, new_aliases);
+
+    unset_variables(&removed_vars, stash, emitter)?;
+    unset_aliases(&removed_aliases, emitter)?;
+    set_variables(&changed_vars, stash, emitter)?;
+    set_aliases(&changed_aliases, emitter)
+}
+
+/// Adds the variables, bin dirs and aliases of the local config of `path` on
+/// top of the ones from the global config, like an entry for a nearer
+/// directory would
+fn add_local(
+    local_config: Option<&LocalConfig>,
+    path: &str,
+    variables: &mut Vec<EnvVariable>,
+    aliases: &mut Vec<EnvAlias>,
+) {
+    let Some(local_config) = local_config else {
+        return;
+    };
+
+    for var in local_config.variables.iter().flatten() {
+        merge_variable(variables, var.clone());
     }
 
-    if let Some(new_dir) = new_dir {
-        set_variables(
-            &new_dir.variables,
-            Some(&new_dir.load_from),
-            Some(&new_path),
-        );
-        set_aliases(&new_dir.aliases, &cache.shell)?;
-        run_commands(&new_dir.run);
+    if let Some(bin) = local_config.bin.as_deref().filter(|bin| !bin.is_empty()) {
+        merge_variable(variables, bin_variable(path, bin));
     }
 
-    Ok(())
+    for alias in local_config.aliases.iter().flatten() {
+        aliases.retain(|a| a.name != alias.name);
+        aliases.push(alias.clone());
+    }
 }
 
-pub fn run_local(
-    old_local_config: Option<&LocalConfig>,
-    new_local_config: Option<&LocalConfig>,
-    shell: &str,
+/// Moves from the environment of the old directory to the one of the new
+/// directory, each made of the global config and the local config of the
+/// directory if it has one
+/// Both are applied in a single transition so list variables from the local
+/// config combine with the ones from the global config
+pub fn run(
+    cache: &Cache,
+    (old_path, old_local_config): (String, Option<&LocalConfig>),
+    (new_path, new_local_config): (String, Option<&LocalConfig>),
+    stash: &mut Stash,
+    command_cache: &mut CommandCache,
+    emitter: &mut Emitter,
 ) -> Result<()> {
-    if old_local_config.is_none() && new_local_config.is_none() {
+    // Running for the same directory happens when the shell is first loaded,
+    // nothing has been set yet so everything for the directory is applied
+    let old_dir: Option<DirCache> = if old_path == new_path {
+        None
+    } else {
+        cache.get(&old_path)
+    };
+    let new_dir: Option<DirCache> = cache.get(&new_path);
+
+    if old_dir.is_none()
+        && new_dir.is_none()
+        && old_local_config.is_none()
+        && new_local_config.is_none()
+    {
         return Ok(());
     }
 
-    if let Some(old_local_config) = old_local_config {
-        if let Some(vars) = &old_local_config.variables {
-            unset_variables(vars, None, None);
-        }
-
-        if let Some(aliases) = &old_local_config.aliases {
-            unset_aliases(aliases);
-        }
+    // Leave hooks run before anything is unset so they still see the
+    // environment of the scope they belong to
+    if old_dir.is_some() {
+        run_commands(&get_on_leave_commands(cache, &old_path, &new_path), emitter);
     }
 
-    if let Some(new_local_config) = new_local_config {
-        if let Some(vars) = &new_local_config.variables {
-            set_variables(vars, None, None);
-        }
+    let old_dir = old_dir.unwrap_or_default();
+    let new_dir = new_dir.unwrap_or_default();
+
+    let mut old_vars = get_variables(
+        &old_dir.variables,
+        Some(&old_dir.load_from[..]),
+        Some(&old_path),
+        false,
+    );
+    let mut old_aliases = old_dir.aliases;
+    add_local(old_local_config, &old_path, &mut old_vars, &mut old_aliases);
+
+    let mut new_vars = get_variables(
+        &new_dir.variables,
+        Some(&new_dir.load_from[..]),
+        Some(&new_path),
+        true,
+    );
+    let mut new_aliases = new_dir.aliases;
+    add_local(new_local_config, &new_path, &mut new_vars, &mut new_aliases);
+
+    let old_vars = current_values(old_vars);
+    let new_vars = resolve_values(new_vars, &new_path, command_cache);
+    let (old_vars, new_vars) = interpolate(old_vars, new_vars, stash);
+
+    transition(
+        (&old_vars, &old_aliases),
+        (&new_vars, &new_aliases),
+        stash,
+        emitter,
+    )?;
+    run_commands(&new_dir.run, emitter);
+    if let Some(commands) = new_local_config.and_then(|c| c.commands.as_ref()) {
+        run_commands(commands, emitter);
+    }
 
-        if let Some(aliases) = &new_local_config.aliases {
-            // This might be the only error worth reporting to the user
-            // since it means that they have misconfigured cdwe
-            if let Err(err) = set_aliases(aliases, shell) {
-                eprintln!("ERROR: misconfigured shell { }", err);
-            }
-        }
+    Ok(())
+}
 
-        if let Some(commands) = &new_local_config.commands {
-            run_commands(commands);
-        }
+/// Runs the on leave commands of the local config of the old directory
+/// This has to happen before `run` so the innermost scope is left first
+pub fn leave_local(old_local_config: Option<&LocalConfig>, emitter: &mut Emitter) {
+    if let Some(on_leave) = old_local_config.and_then(|c| c.on_leave.as_ref()) {
+        run_commands(on_leave, emitter);
     }
-
-    return Ok(());
 }
 
 #[cfg(test)]
 mod tests {
     #[test]
-    fn test_parse_env_file() {
-        use super::parse_env_file;
+    fn test_get_on_leave_commands() {
+        use super::get_on_leave_commands;
+        use crate::cache::Cache;
+        use crate::config::Config;
+
+        let config = Config::from_str(
+            r#"
+            [[directory]]
+            path = "/dev"
+            on_leave = ["leave dev"]
+
+            [[directory]]
+            path = "/dev/project"
+            on_leave = ["leave project"]
+
+            [[directory]]
+            path = "/dev/project/src"
+            on_leave = ["leave src"]
+            "#,
+        )
+        .unwrap();
+        let cache = Cache::from_config(&config, "hash").unwrap();
+
+        assert_eq!(
+            get_on_leave_commands(&cache, "/dev/project/src", "/"),
+            vec!["leave src", "leave project", "leave dev"]
+        );
+        assert_eq!(
+            get_on_leave_commands(&cache, "/dev/project/src", "/dev/other"),
+            vec!["leave src", "leave project"]
+        );
+        assert!(get_on_leave_commands(&cache, "/dev/project", "/dev/project/src").is_empty());
+    }
+
+    #[test]
+    fn test_diff_variables() {
+        use super::diff_variables;
         use crate::config::EnvVariable;
 
-        let test_content = "\
-            # THIS IS A TEST COMMMENT\n\
-            TEST_VAR=true\n\
-            ANOTHER_VAR=123\n\
-            QUOTED_VAR=\"test\"\n\
-            # ANOTHER TEST COMMENT\n\
-            SINGLE_QUOTED_VAR='test'\n\
-            ANOTHER_VAR=hello world this is a test\n\
-        ";
-
-        let expected: Vec<EnvVariable> = vec![
-            EnvVariable {
-                name: "TEST_VAR".to_string(),
-                value: "true".to_string(),
-            },
-            EnvVariable {
-                name: "ANOTHER_VAR".to_string(),
-                value: "123".to_string(),
-            },
-            EnvVariable {
-                name: "QUOTED_VAR".to_string(),
-                value: "test".to_string(),
-            },
-            EnvVariable {
-                name: "SINGLE_QUOTED_VAR".to_string(),
-                value: "test".to_string(),
-            },
-            EnvVariable {
-                name: "ANOTHER_VAR".to_string(),
-                value: "hello world this is a test".to_string(),
-            },
+        let var = |name: &str, value: &str| EnvVariable {
+            name: name.to_string(),
+            value: value.to_string(),
+            ..Default::default()
+        };
+        let old = vec![
+            var("SHARED", "1"),
+            var("CHANGED", "old"),
+            var("REMOVED", "1"),
         ];
+        let new = vec![var("SHARED", "1"), var("CHANGED", "new"), var("ADDED", "1")];
+
+        let (removed, changed) = diff_variables(&old, &new);
+        assert_eq!(removed, vec![var("REMOVED", "1")]);
+        assert_eq!(changed, vec![var("CHANGED", "new"), var("ADDED", "1")]);
+    }
+
+    #[test]
+    fn test_interpolate_self_reference_between_siblings() {
+        use super::{diff_variables, interpolate};
+        use crate::config::EnvVariable;
+        use crate::stash::Stash;
+
+        let var = |value: &str| EnvVariable {
+            name: "CDWE_RUN_SELF_REF".to_string(),
+            value: value.to_string(),
+            ..Default::default()
+        };
+
+        // Entering /a from outside of cdwe
+        std::env::set_var("CDWE_RUN_SELF_REF", "/orig");
+        let mut stash = Stash::default();
+        let (_, new) = interpolate(vec![], vec![var("$CDWE_RUN_SELF_REF:/a")], &stash);
+        assert_eq!(new, vec![var("/orig:/a")]);
+        stash.save("CDWE_RUN_SELF_REF");
+        std::env::set_var("CDWE_RUN_SELF_REF", "/orig:/a");
+
+        // Moving from /a to its sibling /b and back again
+        let (old, new) = interpolate(
+            vec![var("$CDWE_RUN_SELF_REF:/a")],
+            vec![var("$CDWE_RUN_SELF_REF:/b")],
+            &stash,
+        );
+        assert_eq!(new, vec![var("/orig:/b")]);
+        assert_eq!(diff_variables(&old, &new).1, vec![var("/orig:/b")]);
+        std::env::set_var("CDWE_RUN_SELF_REF", "/orig:/b");
+
+        let (old, new) = interpolate(
+            vec![var("$CDWE_RUN_SELF_REF:/b")],
+            vec![var("$CDWE_RUN_SELF_REF:/a")],
+            &stash,
+        );
+        assert_eq!(old, vec![var("/orig:/b")]);
+        assert_eq!(new, vec![var("/orig:/a")]);
+    }
 
-        assert_eq!(parse_env_file(test_content, "/.env").unwrap(), expected);
+    #[test]
+    fn test_list_variables_keep_changes_made_in_between() {
+        use super::{set_variables, unset_variables};
+        use crate::cmd::{Emitter, Shell};
+        use crate::config::EnvVariable;
+        use crate::stash::Stash;
+
+        let list = |prepend: &str| EnvVariable {
+            name: "CDWE_RUN_LIST".to_string(),
+            prepend: Some(vec![prepend.to_string()]),
+            ..Default::default()
+        };
+        let mut stash = Stash::default();
+        let mut emitter = Emitter::new(Shell::Bash);
+
+        std::env::set_var("CDWE_RUN_LIST", "/usr/bin");
+        set_variables(&[list("/a/bin")], &mut stash, &mut emitter).unwrap();
+
+        // Changed by the user inside the directory, then moving to a sibling
+        std::env::set_var("CDWE_RUN_LIST", "/venv/bin:/a/bin:/usr/bin");
+        set_variables(&[list("/b/bin")], &mut stash, &mut emitter).unwrap();
+
+        std::env::set_var("CDWE_RUN_LIST", "/b/bin:/venv/bin:/usr/bin");
+        unset_variables(&[list("/b/bin")], &mut stash, &mut emitter).unwrap();
+
+        assert_eq!(
+            emitter.lines(),
+            vec![
+                "export CDWE_RUN_LIST='/a/bin:/usr/bin'",
+                "export CDWE_RUN_LIST='/b/bin:/venv/bin:/usr/bin'",
+                "export CDWE_RUN_LIST='/venv/bin:/usr/bin'",
+            ]
+        );
+        assert!(stash.is_empty());
+    }
+
+    #[test]
+    fn test_diff_aliases() {
+        use super::diff_aliases;
+        use crate::config::EnvAlias;
+
+        let alias = |name: &str, command: &str| EnvAlias {
+            name: name.to_string(),
+            commands: vec![command.to_string()],
+            description: None,
+        };
+        let old = vec![alias("build", "make"), alias("test", "make test")];
+        let new = vec![alias("build", "make"), alias("test", "cargo test")];
+
+        let (removed, changed) = diff_aliases(&old, &new);
+        assert!(removed.is_empty());
+        assert_eq!(changed, vec![alias("test", "cargo test")]);
     }
 }
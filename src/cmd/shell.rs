@@ -1,5 +1,7 @@
+use crate::paths;
 use anyhow::{Context, Result};
 use clap::ValueEnum;
+use std::fmt;
 use std::path::Path;
 
 #[derive(Debug, ValueEnum, Clone)]
@@ -7,14 +9,22 @@ pub enum Shell {
     Bash,
     Fish,
     Zsh,
+    Nu,
+    Pwsh,
+    /// Any POSIX compatible shell such as dash, ksh or busybox sh
+    #[value(alias = "sh", alias = "dash", alias = "ksh")]
+    Posix,
 }
 
-impl ToString for Shell {
-    fn to_string(&self) -> String {
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Shell::Bash => "bash".to_string(),
-            Shell::Fish => "fish".to_string(),
-            Shell::Zsh => "zsh".to_string(),
+            Shell::Bash => write!(f, "bash"),
+            Shell::Fish => write!(f, "fish"),
+            Shell::Zsh => write!(f, "zsh"),
+            Shell::Nu => write!(f, "nu"),
+            Shell::Pwsh => write!(f, "pwsh"),
+            Shell::Posix => write!(f, "posix"),
         }
     }
 }
@@ -28,7 +38,7 @@ impl Shell {
                 .to_str()
                 .context("failed to get bash config path")?
                 .to_string()),
-            Shell::Fish => Ok(std::path::Path::join(home, "/config/fish/config.fish")
+            Shell::Fish => Ok(std::path::Path::join(home, ".config/fish/config.fish")
                 .to_str()
                 .context("failed to get fish config path")?
                 .to_string()),
@@ -36,6 +46,26 @@ impl Shell {
                 .to_str()
                 .context("failed to get zsh config path")?
                 .to_string()),
+            Shell::Nu => Ok(std::path::Path::join(home, ".config/nushell/config.nu")
+                .to_str()
+                .context("failed to get nu config path")?
+                .to_string()),
+            Shell::Pwsh => Ok(std::path::Path::join(
+                home,
+                ".config/powershell/Microsoft.PowerShell_profile.ps1",
+            )
+            .to_str()
+            .context("failed to get pwsh config path")?
+            .to_string()),
+            // Interactive POSIX shells read the file in $ENV, if there is none
+            // fall back to the login profile
+            Shell::Posix => match std::env::var("ENV") {
+                Ok(env_file) if !env_file.is_empty() => Ok(env_file),
+                _ => Ok(std::path::Path::join(home, ".profile")
+                    .to_str()
+                    .context("failed to get posix config path")?
+                    .to_string()),
+            },
         }
     }
 
@@ -44,6 +74,9 @@ impl Shell {
             "bash" => Ok(Shell::Bash),
             "fish" => Ok(Shell::Fish),
             "zsh" => Ok(Shell::Zsh),
+            "nu" => Ok(Shell::Nu),
+            "pwsh" => Ok(Shell::Pwsh),
+            "posix" | "sh" | "dash" | "ksh" => Ok(Shell::Posix),
             _ => Err(anyhow::anyhow!("invalid shell")),
         }
     }
@@ -53,25 +86,49 @@ impl Shell {
             Shell::Bash => include_str!("../../shells/cdwe_bash.txt").to_string(),
             Shell::Fish => include_str!("../../shells/cdwe_fish.txt").to_string(),
             Shell::Zsh => include_str!("../../shells/cdwe_zsh.txt").to_string(),
+            Shell::Nu => include_str!("../../shells/cdwe_nu.txt").to_string(),
+            Shell::Pwsh => include_str!("../../shells/cdwe_pwsh.txt").to_string(),
+            Shell::Posix => include_str!("../../shells/cdwe_posix.txt").to_string(),
+        }
+    }
+
+    fn get_script_extension(&self) -> &str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Fish => "fish",
+            Shell::Zsh => "zsh",
+            Shell::Nu => "nu",
+            Shell::Pwsh => "ps1",
+            Shell::Posix => "sh",
         }
     }
 
     pub fn get_shell_script_target(&self) -> Result<String> {
-        let home_var = std::env::var("HOME").context("no $HOME set")?;
-        let home = Path::new(&home_var);
+        paths::shell_script_path(self.get_script_extension())
+    }
+
+    /// Where versions before XDG support installed the shell script
+    pub fn get_legacy_shell_script_target(&self) -> Result<String> {
+        paths::legacy_shell_script_path(self.get_script_extension())
+    }
+
+    /// The line added to the shell config that loads the cdwe script
+    pub fn get_source_string(&self, shell_script_target: &str) -> String {
         match self {
-            Shell::Bash => Ok(std::path::Path::join(home, ".cdwe.bash")
-                .to_str()
-                .context("failed to get bash target")?
-                .to_string()),
-            Shell::Fish => Ok(std::path::Path::join(home, ".cdwe.fish")
-                .to_str()
-                .context("failed to get fish target")?
-                .to_string()),
-            Shell::Zsh => Ok(std::path::Path::join(home, ".cdwe.zsh")
-                .to_str()
-                .context("failed to get zsh target")?
-                .to_string()),
+            Shell::Bash | Shell::Zsh | Shell::Posix => format!(
+                "if [ -f '{}' ]; then . '{}'; fi",
+                shell_script_target, shell_script_target
+            ),
+            Shell::Fish => format!(
+                "if test -f '{}'; source '{}'; end",
+                shell_script_target, shell_script_target
+            ),
+            // Nushell resolves sources while parsing, so the script has to exist
+            Shell::Nu => format!("source '{}'", shell_script_target),
+            Shell::Pwsh => format!(
+                "if (Test-Path '{}') {{ . '{}' }}",
+                shell_script_target, shell_script_target
+            ),
         }
     }
 
@@ -80,17 +137,74 @@ impl Shell {
             Shell::Bash => "builtin cd".to_string(),
             Shell::Fish => "cd".to_string(),
             Shell::Zsh => "builtin cd".to_string(),
+            Shell::Nu => "cd".to_string(),
+            Shell::Posix => "cd".to_string(),
+            Shell::Pwsh => "Microsoft.PowerShell.Management\\Set-Location".to_string(),
         }
     }
 
-    pub fn get_alias_command(&self) -> (String, String) {
+    /// Quotes a value so the shell reads it back literally
+    pub fn quote(&self, value: &str) -> String {
         match self {
-            Shell::Zsh => ("{{{alias_name}}}() {\n".to_string(), "}\n".to_string()),
-            Shell::Bash => ("{{{alias_name}}}() {\n".to_string(), "}\n".to_string()),
-            Shell::Fish => (
-                "function {{{alias_name}}} -d \"{}\"\n".to_string(),
-                "end\n".to_string(),
-            ),
+            // Nothing is special inside single quotes except the closing quote
+            Shell::Bash | Shell::Zsh | Shell::Posix => {
+                format!("'{}'", value.replace('\'', "'\\''"))
+            }
+            // Fish also treats \\ and \' as escapes inside single quotes
+            Shell::Fish => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+            // Nothing is escaped in raw strings, they only end at a quote
+            // followed by as many # as they started with
+            Shell::Nu => {
+                let mut hashes = String::new();
+                while value.contains(&format!("'{}", hashes)) {
+                    hashes.push('#');
+                }
+
+                if hashes.is_empty() {
+                    format!("'{}'", value)
+                } else {
+                    format!("r{}'{}'{}", hashes, value, hashes)
+                }
+            }
+            // Single quotes, including the typographic ones powershell also
+            // accepts, are escaped by doubling them
+            Shell::Pwsh => {
+                let mut quoted = String::from("'");
+                for c in value.chars() {
+                    if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+                        quoted.push(c);
+                    }
+                    quoted.push(c);
+                }
+                quoted.push('\'');
+                quoted
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Shell;
+
+    #[test]
+    fn test_quote() {
+        let value = "it's \"$HOME\" `id` \\n\nnext";
+        assert_eq!(
+            Shell::Bash.quote(value),
+            "'it'\\''s \"$HOME\" `id` \\n\nnext'"
+        );
+        assert_eq!(Shell::Zsh.quote(""), "''");
+        assert_eq!(
+            Shell::Fish.quote(value),
+            "'it\\'s \"$HOME\" `id` \\\\n\nnext'"
+        );
+        assert_eq!(Shell::Nu.quote("a \\n \"b\""), "'a \\n \"b\"'");
+        assert_eq!(Shell::Nu.quote(value), format!("r#'{}'#", value));
+        assert_eq!(Shell::Nu.quote("'#"), "r##''#'##");
+        assert_eq!(
+            Shell::Pwsh.quote("it's $env:HOME `n \u{2019}"),
+            "'it''s $env:HOME `n \u{2019}\u{2019}'"
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use crate::cmd::Shell;
-use anyhow::{Context, Result};
+use crate::utils::{expand_path, resolve_include};
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 pub type EnvVariableVec = Vec<EnvVariable>;
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -17,24 +19,66 @@ impl From<EnvVariableStruct> for EnvVariableVec {
             EnvVariableStruct::EnvVariableVec(dir_env_variable) => dir_env_variable,
             EnvVariableStruct::HashMap(hash_map) => hash_map
                 .into_iter()
-                .map(|(name, value)| EnvVariable { name, value })
+                .map(|(name, value)| EnvVariable {
+                    name,
+                    value,
+                    ..Default::default()
+                })
                 .collect(),
         }
     }
 }
 
+/// An .env file to load, either just its path or a table with options
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum LoadFromStruct {
+    Path(String),
+    LoadFromEntry(LoadFromEntry),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub struct LoadFromEntry {
+    pub path: String,
+    /// Optional files may be missing without a warning
+    #[serde(default)]
+    pub optional: bool,
+    /// Encrypted files are decrypted with the local key before parsing
+    #[serde(default)]
+    pub encrypted: bool,
+}
+
+impl From<LoadFromStruct> for LoadFromEntry {
+    fn from(load_from: LoadFromStruct) -> Self {
+        match load_from {
+            LoadFromStruct::Path(path) => LoadFromEntry {
+                path,
+                ..Default::default()
+            },
+            LoadFromStruct::LoadFromEntry(entry) => entry,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct LocalConfig {
     pub variables: Option<Vec<EnvVariable>>,
     pub aliases: Option<Vec<EnvAlias>>,
     pub commands: Option<Vec<String>>,
+    pub on_leave: Option<Vec<String>>,
+    /// Directories prepended to `PATH`, relative to the local config
+    pub bin: Option<Vec<String>>,
 }
 
 impl LocalConfig {
     pub fn from_str(content: &str) -> Result<Self> {
-        let config: LocalConfig =
+        let mut config: LocalConfig =
             toml::from_str(content).with_context(|| "Could not parse local config file")?;
 
+        for entry in config.bin.iter_mut().flatten() {
+            *entry = expand_path(entry)?;
+        }
+
         Ok(config)
     }
 }
@@ -42,7 +86,27 @@ impl LocalConfig {
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Config {
     pub config: Option<GlobalConfig>,
-    #[serde(rename = "directory")]
+    /// Other config files merged into this one, may use `~` and globs
+    pub include: Option<Vec<String>>,
+    #[serde(rename = "directory", default)]
+    pub directories: Vec<EnvDirectory>,
+    #[serde(rename = "env_variable")]
+    pub variables: Option<Vec<DirEnvVariable>>,
+    #[serde(rename = "command")]
+    pub commands: Option<Vec<EnvCommand>>,
+    #[serde(rename = "env_file")]
+    pub files: Option<Vec<EnvFile>>,
+    #[serde(rename = "alias")]
+    pub aliases: Option<Vec<DirectoryEnvAlias>>,
+    #[serde(rename = "profile", default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Profile>,
+}
+
+/// Entries that only apply while the profile is active, a `[profile.<name>]`
+/// table takes the same entries as the top level of the config
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Profile {
+    #[serde(rename = "directory", default)]
     pub directories: Vec<EnvDirectory>,
     #[serde(rename = "env_variable")]
     pub variables: Option<Vec<DirEnvVariable>>,
@@ -54,6 +118,41 @@ pub struct Config {
     pub aliases: Option<Vec<DirectoryEnvAlias>>,
 }
 
+/// Files that can't be canonicalized are kept as they are, reading them
+/// reports the problem
+fn canonicalize(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn extend<T>(entries: &mut Option<Vec<T>>, other: Option<Vec<T>>) {
+    if let Some(other) = other {
+        entries.get_or_insert_with(Vec::new).extend(other);
+    }
+}
+
+impl Profile {
+    fn merge(&mut self, other: Profile) {
+        self.directories.extend(other.directories);
+        extend(&mut self.variables, other.variables);
+        extend(&mut self.commands, other.commands);
+        extend(&mut self.files, other.files);
+        extend(&mut self.aliases, other.aliases);
+    }
+}
+
+impl From<Profile> for Config {
+    fn from(profile: Profile) -> Self {
+        Config {
+            directories: profile.directories,
+            variables: profile.variables,
+            commands: profile.commands,
+            files: profile.files,
+            aliases: profile.aliases,
+            ..Default::default()
+        }
+    }
+}
+
 impl Config {
     pub fn default_for_shell(shell: Shell) -> Self {
         Config {
@@ -61,17 +160,22 @@ impl Config {
                 shell: Some(shell.to_string()),
                 ..Default::default()
             }),
+            include: None,
             directories: vec![EnvDirectory {
                 path: "~".to_string(),
+                path_regex: None,
                 vars: None,
                 load_from: None,
                 run: None,
+                on_leave: None,
                 aliases: None,
+                bin: None,
             }],
             variables: None,
             commands: None,
             files: None,
             aliases: None,
+            profiles: HashMap::new(),
         }
     }
 }
@@ -83,6 +187,9 @@ pub struct GlobalConfig {
     pub env_hints: Option<bool>,
     pub run_hints: Option<bool>,
     pub alias_hints: Option<bool>,
+    /// Resolve symlinks in configured paths and in the directories cd'ed
+    /// into, so a symlinked checkout matches the entry of its target
+    pub resolve_symlinks: Option<bool>,
 }
 
 impl Default for GlobalConfig {
@@ -93,29 +200,43 @@ impl Default for GlobalConfig {
             env_hints: Some(true),
             run_hints: Some(true),
             alias_hints: Some(true),
+            resolve_symlinks: Some(false),
         }
     }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct EnvDirectory {
+    #[serde(default)]
     pub path: String,
+    /// Regular expression matched against the whole directory path, used
+    /// instead of `path`
+    pub path_regex: Option<String>,
     pub vars: Option<EnvVariableStruct>,
-    pub load_from: Option<Vec<String>>,
+    pub load_from: Option<Vec<LoadFromStruct>>,
     pub run: Option<Vec<String>>,
+    /// Commands run when leaving the directory and its subdirectories
+    pub on_leave: Option<Vec<String>>,
     pub aliases: Option<Vec<EnvAlias>>,
+    /// Directories prepended to `PATH`, relative ones are relative to the
+    /// directory that matched
+    pub bin: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct EnvAlias {
     pub name: String,
     pub commands: Vec<String>,
+    /// Shown by shells that support describing functions, like fish
+    pub description: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DirectoryEnvAlias {
     pub name: String,
     pub commands: Vec<String>,
+    pub description: Option<String>,
+    #[serde(alias = "dirs")]
     pub paths: Vec<String>,
 }
 
@@ -126,10 +247,30 @@ pub struct DirEnvVariable {
     pub dirs: Vec<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
 pub struct EnvVariable {
     pub name: String,
+    #[serde(default)]
     pub value: String,
+    /// Shell command whose output becomes the value, run in the directory
+    pub value_from_cmd: Option<String>,
+    /// Seconds the output of `value_from_cmd` is reused before running it again
+    pub cache_ttl: Option<u64>,
+    /// Seconds `value_from_cmd` may run before it's killed, defaults to 5
+    pub timeout: Option<u64>,
+    /// File whose trimmed content becomes the value, relative to the directory
+    pub value_from_file: Option<String>,
+    /// Allows `value_from_file` to read files anyone on the system can read
+    #[serde(default)]
+    pub allow_world_readable: bool,
+    /// Entries put in front of the inherited value of a list variable like `PATH`
+    pub prepend: Option<Vec<String>>,
+    /// Entries put after the inherited value of a list variable
+    pub append: Option<Vec<String>>,
+    /// Entries taken out of the inherited value of a list variable
+    pub remove: Option<Vec<String>>,
+    /// Separates the entries of a list variable, defaults to `:`
+    pub separator: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -141,6 +282,8 @@ pub struct EnvCommand {
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct EnvFile {
     pub load_from: String,
+    pub optional: Option<bool>,
+    pub encrypted: Option<bool>,
     pub dirs: Vec<String>,
 }
 
@@ -161,4 +304,98 @@ impl Config {
 
         Ok(config)
     }
+
+    /// Parses the config at `path` and merges in the files it includes
+    /// Returns the merged config and the include patterns of every file read
+    pub fn from_str_with_includes(content: &str, path: &str) -> Result<(Self, Vec<String>)> {
+        let mut config = Config::from_str(content)?;
+        let path = Path::new(path);
+        let mut includes = vec![];
+        let mut visited = vec![path.to_path_buf()];
+
+        config.merge_includes(
+            path.parent().unwrap_or(Path::new("/")),
+            &mut includes,
+            &mut visited,
+            &mut |path, content| {
+                toml::from_str(content)
+                    .with_context(|| format!("Could not parse config file at {}", path.display()))
+            },
+        )?;
+
+        Ok((config, includes))
+    }
+
+    /// Merges the configs matched by the `include` patterns of this config and
+    /// the configs they include in turn, parsed with `parse`
+    /// Relative patterns are resolved from `base_dir` and added to `includes`.
+    /// Files in `visited` have already been merged and are skipped, so include
+    /// cycles end. Paths are compared once canonicalized, so a file reached
+    /// through `..` or a symlink is still recognized
+    pub fn merge_includes(
+        &mut self,
+        base_dir: &Path,
+        includes: &mut Vec<String>,
+        visited: &mut Vec<PathBuf>,
+        parse: &mut dyn FnMut(&Path, &str) -> Result<Config>,
+    ) -> Result<()> {
+        for pattern in self.include.take().unwrap_or_default() {
+            let pattern = base_dir
+                .join(expand_path(&pattern)?)
+                .to_string_lossy()
+                .to_string();
+            includes.push(pattern.clone());
+
+            for path in resolve_include(&pattern)? {
+                let canonical = canonicalize(&path);
+                if visited.iter().any(|v| canonicalize(v) == canonical) {
+                    continue;
+                }
+                visited.push(canonical);
+
+                let content = std::fs::read_to_string(&path).with_context(|| {
+                    format!("Could not read included config {}", path.display())
+                })?;
+                let mut included = parse(&path, &content)?;
+                included.merge_includes(
+                    path.parent().unwrap_or(base_dir),
+                    includes,
+                    visited,
+                    parse,
+                )?;
+                self.merge(included);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends the entries of an included config, its `[config]` table is
+    /// ignored since global options only come from the main config
+    fn merge(&mut self, other: Config) {
+        self.directories.extend(other.directories);
+        extend(&mut self.variables, other.variables);
+        extend(&mut self.commands, other.commands);
+        extend(&mut self.files, other.files);
+        extend(&mut self.aliases, other.aliases);
+
+        for (name, profile) in other.profiles {
+            self.profiles.entry(name).or_default().merge(profile);
+        }
+    }
+
+    /// Appends the entries of the named profiles, in order, so a profile
+    /// listed later can override the ones before it
+    pub fn apply_profiles(&mut self, names: &[String]) -> Result<()> {
+        for name in names {
+            let profile = self
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("unknown profile {}", name))?;
+            self.merge(profile.into());
+        }
+
+        Ok(())
+    }
 }
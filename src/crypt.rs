@@ -0,0 +1,113 @@
+use crate::paths;
+use age::armor::{ArmoredWriter, Format};
+use age::secrecy::ExposeSecret;
+use age::x25519::{Identity, Recipient};
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Location of the age key used for encrypted .env files
+pub fn get_key_path() -> Result<PathBuf> {
+    Ok(PathBuf::from(paths::key_path()?))
+}
+
+/// Parses a key file in the format written by `age-keygen`, comments are
+/// ignored and the first key is used
+pub fn parse_key(content: &str) -> Result<Identity> {
+    let key = content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or_else(|| anyhow!("no key found"))?;
+
+    key.parse::<Identity>()
+        .map_err(|err| anyhow!("invalid key: {}", err))
+}
+
+pub fn load_key(path: &Path) -> Result<Identity> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read key file at {}", path.display()))?;
+
+    parse_key(&content).with_context(|| format!("Could not load key file at {}", path.display()))
+}
+
+/// Generates a new key and writes it to `path`, readable only by the owner
+pub fn generate_key(path: &Path) -> Result<Identity> {
+    let identity = Identity::generate();
+    let content = format!(
+        "# public key: {}\n{}\n",
+        identity.to_public(),
+        identity.to_string().expose_secret()
+    );
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Could not create {}", parent.display()))?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .with_context(|| format!("Could not write key file at {}", path.display()))?;
+
+    Ok(identity)
+}
+
+/// Encrypts `content` to every recipient as ascii armored text, so encrypted
+/// files can be committed and diffed like any other text file
+pub fn encrypt(content: &[u8], recipients: &[Recipient]) -> Result<String> {
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+            .context("Could not encrypt, no recipients")?;
+
+    let mut encrypted = vec![];
+    let mut writer = encryptor.wrap_output(ArmoredWriter::wrap_output(
+        &mut encrypted,
+        Format::AsciiArmor,
+    )?)?;
+    writer.write_all(content)?;
+    writer.finish()?.finish()?;
+
+    Ok(String::from_utf8(encrypted)?)
+}
+
+/// Decrypts armored or binary age encrypted content
+pub fn decrypt(content: &[u8], identity: &Identity) -> Result<Vec<u8>> {
+    age::decrypt(identity, content).map_err(|err| anyhow!("could not decrypt: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt, encrypt, parse_key};
+    use age::secrecy::ExposeSecret;
+    use age::x25519::Identity;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let identity = Identity::generate();
+        let other = Identity::generate();
+        let key_file = format!(
+            "# created: today\n# public key: {}\n{}\n",
+            identity.to_public(),
+            identity.to_string().expose_secret()
+        );
+        let parsed = parse_key(&key_file).unwrap();
+        assert_eq!(
+            parsed.to_public().to_string(),
+            identity.to_public().to_string()
+        );
+        assert!(parse_key("# only a comment\n").is_err());
+
+        let encrypted = encrypt(b"SECRET=1\n", &[identity.to_public()]).unwrap();
+        assert!(encrypted.starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+        assert_eq!(
+            decrypt(encrypted.as_bytes(), &parsed).unwrap(),
+            b"SECRET=1\n".to_vec()
+        );
+        assert!(decrypt(encrypted.as_bytes(), &other).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use crate::cmd::is_valid_var_name;
+use crate::config::EnvVariable;
+use crate::utils::trim_quotes;
+use anyhow::{anyhow, Result};
+
+/// Walks the content of an .env file while keeping track of the line number,
+/// since quoted values may span several lines
+struct Parser<'a> {
+    content: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.content[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    /// The remainder of the current line, without the line break
+    fn current_line(&self) -> &'a str {
+        let rest = &self.content[self.pos..];
+        rest.split('\n').next().unwrap_or(rest)
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.next() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    /// Reads up to the closing `quote`, decoding escapes if `escapes` is set
+    /// A `$` that has to stay literal is kept escaped as `\$` for interpolation
+    /// Returns `None` if the quote is never closed
+    fn quoted(&mut self, quote: char, escapes: bool) -> Option<String> {
+        let mut value = String::new();
+
+        while let Some(c) = self.next() {
+            match c {
+                c if c == quote => return Some(value),
+                '\\' if escapes => match self.next()? {
+                    'n' => value.push('\n'),
+                    'r' => value.push('\r'),
+                    't' => value.push('\t'),
+                    '$' => value.push_str("\\$"),
+                    c @ ('"' | '\\' | '\n') => value.push(c),
+                    c => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                },
+                '$' if !escapes => value.push_str("\\$"),
+                c => value.push(c),
+            }
+        }
+
+        None
+    }
+
+    /// Reads an unquoted value up to the end of the line or an inline comment
+    fn unquoted(&mut self) -> String {
+        let mut value = String::new();
+
+        while let Some(c) = self.peek() {
+            if c == '\n' || (c == '#' && value.ends_with([' ', '\t'])) {
+                break;
+            }
+            value.push(c);
+            self.next();
+        }
+
+        value.trim_end().to_string()
+    }
+
+    /// Consumes the rest of the line after a value, which can only be
+    /// whitespace or a comment
+    fn end_of_line(&mut self) -> bool {
+        let rest = self.current_line().trim_start();
+        let valid = rest.is_empty() || rest.starts_with('#');
+        self.skip_line();
+        valid
+    }
+}
+
+/// Parses the content of an .env file following the common dotenv rules
+/// ```text
+/// # comments and blank lines are ignored
+/// SOME_VAR=test # unquoted values end at an inline comment
+/// export ANOTHER_VAR="test\nwith escapes"
+/// SINGLE_QUOTED='taken $literally'
+/// INTERPOLATED="${SOME_VAR}/path"
+/// MULTI_LINE="first
+/// second"
+/// ```
+///
+/// Values are returned ready for interpolation, literal `$` signs from single
+/// quoted values or `\$` escapes are returned as `\$`
+/// Errors point at the line the invalid entry starts on
+pub fn parse_env_file(content: &str, file_name: &str) -> Result<Vec<EnvVariable>> {
+    let mut parser = Parser {
+        content,
+        pos: 0,
+        line: 1,
+    };
+    let mut vars = vec![];
+
+    while parser.peek().is_some() {
+        let line = parser.current_line();
+        let line_number = parser.line;
+        let error = |reason: &str| {
+            anyhow!(
+                "Invalid line in file: {}:{}: {}: {}",
+                file_name,
+                line_number,
+                reason,
+                line.trim_end_matches('\r')
+            )
+        };
+
+        let trimmed = line.trim_start();
+        if trimmed.trim_end().is_empty() || trimmed.starts_with('#') {
+            parser.skip_line();
+            continue;
+        }
+
+        let entry = trimmed
+            .strip_prefix("export")
+            .filter(|e| e.starts_with([' ', '\t']))
+            .unwrap_or(trimmed);
+        let (key, value) = entry
+            .split_once('=')
+            .ok_or_else(|| error("expected KEY=VALUE"))?;
+
+        let key = trim_quotes(key.trim());
+        if !is_valid_var_name(&key) {
+            return Err(error("invalid variable name"));
+        }
+
+        // Continue from the start of the value, which may run past this line
+        let value = value.trim_start_matches([' ', '\t']);
+        parser.pos = value.as_ptr() as usize - content.as_ptr() as usize;
+
+        let value = match parser.peek() {
+            Some('"') => {
+                parser.next();
+                parser.quoted('"', true)
+            }
+            Some(quote @ ('\'' | '`')) => {
+                parser.next();
+                parser.quoted(quote, false)
+            }
+            _ => Some(parser.unquoted()),
+        }
+        .ok_or_else(|| error("unterminated quoted value"))?;
+
+        if !parser.end_of_line() {
+            return Err(error("unexpected characters after value"));
+        }
+
+        vars.push(EnvVariable {
+            name: key,
+            value,
+            ..Default::default()
+        });
+    }
+
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_env_file;
+    use crate::config::EnvVariable;
+
+    #[test]
+    fn test_parse_env_file() {
+        let test_content = "\
+            # THIS IS A TEST COMMMENT\n\
+            TEST_VAR=true\n\
+            ANOTHER_VAR=123\n\
+            QUOTED_VAR=\"test\"\n\
+            # ANOTHER TEST COMMENT\n\
+            SINGLE_QUOTED_VAR='test'\n\
+            ANOTHER_VAR=hello world this is a test\n\
+        ";
+
+        let expected: Vec<EnvVariable> = vec![
+            EnvVariable {
+                name: "TEST_VAR".to_string(),
+                value: "true".to_string(),
+                ..Default::default()
+            },
+            EnvVariable {
+                name: "ANOTHER_VAR".to_string(),
+                value: "123".to_string(),
+                ..Default::default()
+            },
+            EnvVariable {
+                name: "QUOTED_VAR".to_string(),
+                value: "test".to_string(),
+                ..Default::default()
+            },
+            EnvVariable {
+                name: "SINGLE_QUOTED_VAR".to_string(),
+                value: "test".to_string(),
+                ..Default::default()
+            },
+            EnvVariable {
+                name: "ANOTHER_VAR".to_string(),
+                value: "hello world this is a test".to_string(),
+                ..Default::default()
+            },
+        ];
+
+        assert_eq!(parse_env_file(test_content, "/.env").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_env_file_corpus() {
+        let corpus: &[(&str, &[(&str, &str)])] = &[
+            ("URL=http://x/#frag", &[("URL", "http://x/#frag")]),
+            ("A=value # comment", &[("A", "value")]),
+            ("A=value\t# comment", &[("A", "value")]),
+            (
+                "A=\"quoted # not a comment\" # comment",
+                &[("A", "quoted # not a comment")],
+            ),
+            ("export A=1\nexport\tB=2", &[("A", "1"), ("B", "2")]),
+            ("exported=1", &[("exported", "1")]),
+            ("  A = spaced  ", &[("A", "spaced")]),
+            ("A=", &[("A", "")]),
+            ("A=\"\"", &[("A", "")]),
+            ("A=a=b", &[("A", "a=b")]),
+            (
+                "A=\"line\\nbreak \\\"q\\\" \\\\ \\$ \\x\"",
+                &[("A", "line\nbreak \"q\" \\ \\$ \\x")],
+            ),
+            ("A='no \\n $escapes'", &[("A", "no \\n \\$escapes")]),
+            ("A=`back tick`", &[("A", "back tick")]),
+            (
+                "A=\"first\nsecond\"\nB=2",
+                &[("A", "first\nsecond"), ("B", "2")],
+            ),
+            (
+                "A='first\n# not a comment'",
+                &[("A", "first\n# not a comment")],
+            ),
+            ("A=1\r\nB=2\r\n", &[("A", "1"), ("B", "2")]),
+            ("\n\n  # indented comment\nA=1", &[("A", "1")]),
+        ];
+
+        for (content, expected) in corpus {
+            let expected: Vec<EnvVariable> = expected
+                .iter()
+                .map(|(name, value)| EnvVariable {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                    ..Default::default()
+                })
+                .collect();
+            assert_eq!(
+                parse_env_file(content, ".env").unwrap(),
+                expected,
+                "parsing {:?}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_env_file_errors() {
+        let error = |content: &str| {
+            parse_env_file(content, "/dev/.env")
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            error("# comment\nA=1\n\nNOT A VAR"),
+            "Invalid line in file: /dev/.env:4: expected KEY=VALUE: NOT A VAR"
+        );
+        assert_eq!(
+            error("A=\"multi\nline\"\nB='unterminated\nC=3"),
+            "Invalid line in file: /dev/.env:3: unterminated quoted value: B='unterminated"
+        );
+        assert_eq!(
+            error("A=\"x\" trailing"),
+            "Invalid line in file: /dev/.env:1: unexpected characters after value: A=\"x\" trailing"
+        );
+        assert_eq!(
+            error("MY VAR=1"),
+            "Invalid line in file: /dev/.env:1: invalid variable name: MY VAR=1"
+        );
+        assert_eq!(
+            error("A=1\nBAD-NAME=2"),
+            "Invalid line in file: /dev/.env:2: invalid variable name: BAD-NAME=2"
+        );
+        assert_eq!(
+            error("A.B=1"),
+            "Invalid line in file: /dev/.env:1: invalid variable name: A.B=1"
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use crate::config::EnvVariable;
+use crate::utils::{expand_path, normalize_path};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const DEFAULT_SEPARATOR: &str = ":";
+
+/// List variables modify the value they inherit instead of replacing it
+pub fn is_list(var: &EnvVariable) -> bool {
+    var.prepend.is_some() || var.append.is_some() || var.remove.is_some()
+}
+
+fn concat(first: Option<Vec<String>>, second: Option<Vec<String>>) -> Option<Vec<String>> {
+    match (first, second) {
+        (Some(mut first), Some(second)) => {
+            first.extend(second);
+            Some(first)
+        }
+        (first, second) => first.or(second),
+    }
+}
+
+/// Adds `var` to `variables`, replacing an earlier definition of the same
+/// variable
+/// When both are list variables their operations are combined instead, the
+/// entries of `var` are prepended in front of and appended after the earlier
+/// ones, so nearer directories come first in a `PATH`
+pub fn merge_variable(variables: &mut Vec<EnvVariable>, var: EnvVariable) {
+    if let Some(existing) = variables.iter_mut().find(|v| v.name == var.name) {
+        if is_list(existing) && is_list(&var) {
+            existing.prepend = concat(var.prepend, existing.prepend.take());
+            existing.append = concat(existing.append.take(), var.append);
+            existing.remove = concat(existing.remove.take(), var.remove);
+            existing.separator = var.separator.or(existing.separator.take());
+            return;
+        }
+    }
+
+    variables.retain(|v| v.name != var.name);
+    variables.push(var);
+}
+
+/// Turns the bin dirs configured for `dir` into a `PATH` variable that
+/// prepends them, relative ones are resolved against `dir`
+/// The bin dirs are expected to be expanded already
+pub fn bin_variable(dir: &str, bin: &[String]) -> EnvVariable {
+    let prepend = bin
+        .iter()
+        .map(|entry| normalize_path(&Path::new(dir).join(entry).to_string_lossy()))
+        .collect();
+
+    EnvVariable {
+        name: "PATH".to_string(),
+        prepend: Some(prepend),
+        ..Default::default()
+    }
+}
+
+/// What applying a list variable changed, so leaving can undo exactly that
+/// and keep whatever else happened to the value in the meantime
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct ListChange {
+    separator: String,
+    /// Entries that were prepended or appended
+    added: Vec<String>,
+    /// Entries that were taken out of the value, with the position they had,
+    /// including the ones that were moved
+    removed: Vec<(usize, String)>,
+    /// Whether the variable was set before it was changed
+    was_set: bool,
+}
+
+impl ListChange {
+    /// Takes the added entries out of `value` and puts the removed ones back
+    /// where they were, `None` if the variable wasn't set before and nothing
+    /// is left
+    pub fn undo(&self, value: Option<&str>) -> Option<String> {
+        let mut entries: Vec<String> = value
+            .unwrap_or_default()
+            .split(self.separator.as_str())
+            .filter(|entry| !entry.is_empty() && !self.added.iter().any(|e| e == entry))
+            .map(str::to_string)
+            .collect();
+
+        for (index, entry) in &self.removed {
+            if !entries.contains(entry) {
+                entries.insert((*index).min(entries.len()), entry.clone());
+            }
+        }
+
+        if entries.is_empty() && !self.was_set {
+            return None;
+        }
+        Some(entries.join(&self.separator))
+    }
+}
+
+/// Applies the operations of a list variable to the value it inherits
+/// Entries are expanded like paths, and an entry that is prepended or
+/// appended is moved rather than repeated
+/// Returns the new value along with what changed, see [`ListChange::undo`]
+pub fn apply_list(var: &EnvVariable, base: Option<&str>) -> Result<(String, ListChange)> {
+    let separator = var.separator.as_deref().unwrap_or(DEFAULT_SEPARATOR);
+    let expand = |entries: &Option<Vec<String>>| -> Result<Vec<String>> {
+        entries
+            .iter()
+            .flatten()
+            .map(|entry| expand_path(entry))
+            .collect::<Result<Vec<String>>>()
+            .with_context(|| format!("Could not expand entries of {}", var.name))
+    };
+
+    let prepend = expand(&var.prepend)?;
+    let append = expand(&var.append)?;
+    let remove = expand(&var.remove)?;
+
+    let mut change = ListChange {
+        separator: separator.to_string(),
+        was_set: base.is_some(),
+        ..Default::default()
+    };
+    let mut entries: Vec<String> = vec![];
+    let mut add = |entries: &mut Vec<String>, added: &[String]| {
+        for entry in added {
+            if !entries.contains(entry) && !remove.contains(entry) {
+                entries.push(entry.clone());
+                change.added.push(entry.clone());
+            }
+        }
+    };
+
+    add(&mut entries, &prepend);
+    let base = base
+        .unwrap_or_default()
+        .split(separator)
+        .filter(|entry| !entry.is_empty());
+    for (index, entry) in base.enumerate() {
+        let listed = remove
+            .iter()
+            .chain(&prepend)
+            .chain(&append)
+            .any(|e| e == entry);
+        if listed || entries.iter().any(|e| e == entry) {
+            change.removed.push((index, entry.to_string()));
+        } else {
+            entries.push(entry.to_string());
+        }
+    }
+    add(&mut entries, &append);
+
+    Ok((entries.join(separator), change))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_list, merge_variable};
+    use crate::config::EnvVariable;
+
+    fn list(name: &str, prepend: &[&str], append: &[&str], remove: &[&str]) -> EnvVariable {
+        let entries = |entries: &[&str]| {
+            (!entries.is_empty()).then(|| entries.iter().map(|e| e.to_string()).collect())
+        };
+        EnvVariable {
+            name: name.to_string(),
+            prepend: entries(prepend),
+            append: entries(append),
+            remove: entries(remove),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_apply_list() {
+        let path = list("PATH", &["/opt/bin", "/usr/bin"], &["/late"], &["/old"]);
+        assert_eq!(
+            apply_list(&path, Some("/usr/bin:/bin:/old::/late"))
+                .unwrap()
+                .0,
+            "/opt/bin:/usr/bin:/bin:/late"
+        );
+        assert_eq!(
+            apply_list(&path, None).unwrap().0,
+            "/opt/bin:/usr/bin:/late"
+        );
+
+        let python_path = EnvVariable {
+            separator: Some(";".to_string()),
+            ..list("PYTHONPATH", &[], &["c"], &["a"])
+        };
+        assert_eq!(apply_list(&python_path, Some("a;b")).unwrap().0, "b;c");
+    }
+
+    #[test]
+    fn test_undo_list() {
+        let path = list("PATH", &["/opt/bin", "/usr/bin"], &["/late"], &["/old"]);
+        let base = "/usr/bin:/bin:/old:/late";
+        let (value, change) = apply_list(&path, Some(base)).unwrap();
+        assert_eq!(change.undo(Some(&value)).as_deref(), Some(base));
+
+        // Changes made after the list was applied are kept
+        let changed = format!("/venv/bin:{}:/sbin", value.replace(":/bin", ""));
+        assert_eq!(
+            change.undo(Some(&changed)).as_deref(),
+            Some("/usr/bin:/venv/bin:/old:/late:/sbin")
+        );
+
+        let (value, change) = apply_list(&path, None).unwrap();
+        assert_eq!(change.undo(Some(&value)), None);
+        assert_eq!(change.undo(Some("/extra")).as_deref(), Some("/extra"));
+    }
+
+    #[test]
+    fn test_merge_variable() {
+        let mut vars = vec![];
+        merge_variable(&mut vars, list("PATH", &["/project/bin"], &[], &[]));
+        merge_variable(
+            &mut vars,
+            list("PATH", &["/project/src/bin"], &["/tail"], &[]),
+        );
+        assert_eq!(
+            vars,
+            vec![list(
+                "PATH",
+                &["/project/src/bin", "/project/bin"],
+                &["/tail"],
+                &[]
+            )]
+        );
+
+        let plain = EnvVariable {
+            name: "PATH".to_string(),
+            value: "/only".to_string(),
+            ..Default::default()
+        };
+        merge_variable(&mut vars, plain.clone());
+        assert_eq!(vars, vec![plain]);
+    }
+}
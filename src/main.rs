@@ -1,42 +1,55 @@
 mod cache;
 mod cmd;
 mod config;
+mod crypt;
+mod env_file;
+mod list;
+mod paths;
+mod stash;
 mod utils;
+mod value;
 use anyhow::{Context, Result};
 use clap::Parser;
-use cmd::{init_shell, remove_shell, run, run_local, Cli};
+use cmd::{
+    check, decrypt_file, encrypt_file, get_active_profiles, init_shell, leave_local, list_profiles,
+    migrate, remove_shell, run, use_profiles, Cli, Emitter, ProfileCommands, Shell,
+};
 use config::{Config, LocalConfig};
+use stash::{Stash, STASH_VAR};
+use value::CommandCache;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let matches = Cli::parse();
-    let home = std::env::var("HOME").context("no $HOME set")?;
-    let config_path = format!("{}/{}", &home, "cdwe.toml");
-    let cache_path = format!("{}/{}", &home, ".cdwe_cache.json");
+    let config_path = paths::config_path()?;
+    let cache_path = paths::cache_path()?;
 
     match matches.command {
         cmd::Commands::Init { shell } => init_shell(None, shell.unwrap())?,
         cmd::Commands::Run { old_dir, new_dir } => {
-            let local_config_path = format!("{}/{}", new_dir, "cdwe.toml");
-            let old_local_config_path = format!("{}/{}", old_dir, "cdwe.toml");
-
             let contents = std::fs::read_to_string(&config_path)
                 .with_context(|| format!("Could not read config file at {}", &config_path))?;
-            let config_hash = utils::get_content_hash(&contents);
-            let cache_contents: Option<String> = std::fs::read_to_string(cache_path).ok();
-            let (cache, did_create_cache) =
-                cache::get_or_create_cache(cache_contents.as_deref(), &contents, &config_hash)?;
-            let shell = cache.shell.clone();
+            let cache_contents: Option<String> = std::fs::read_to_string(&cache_path).ok();
+            let (cache, did_create_cache) = cache::get_or_create_cache(
+                cache_contents.as_deref(),
+                &contents,
+                &config_path,
+                &get_active_profiles()?,
+            )?;
+            let mut emitter = Emitter::new(Shell::from_string(&cache.shell)?);
 
-            run(&cache, old_dir, new_dir)?;
-
-            if did_create_cache {
-                cache::write_cache(&cache, &home)?;
-            }
+            let old_dir = cache.resolve_path(&old_dir);
+            let new_dir = cache.resolve_path(&new_dir);
+            let local_config_path = format!("{}/{}", new_dir, "cdwe.toml");
+            let old_local_config_path = format!("{}/{}", old_dir, "cdwe.toml");
 
+            // When the shell is first loaded the old and new directories are the
+            // same, and nothing from the local config has been applied yet
             let old_local_config = match std::fs::read_to_string(&old_local_config_path) {
-                Ok(contents) => Some(LocalConfig::from_str(&contents)?),
-                Err(_) => None,
+                Ok(contents) if old_local_config_path != local_config_path => {
+                    Some(LocalConfig::from_str(&contents)?)
+                }
+                _ => None,
             };
 
             let new_local_config = match std::fs::read_to_string(&local_config_path) {
@@ -44,15 +57,59 @@ async fn main() -> Result<()> {
                 Err(_) => None,
             };
 
-            if old_local_config.is_some() || new_local_config.is_some() {
-                run_local(old_local_config.as_ref(), new_local_config.as_ref(), &shell)?;
+            let mut stash = Stash::from_env();
+            let command_cache_path = paths::command_cache_path()?;
+            let mut command_cache = std::fs::read_to_string(&command_cache_path)
+                .map(|contents| CommandCache::from_str(&contents))
+                .unwrap_or_default();
+
+            leave_local(old_local_config.as_ref(), &mut emitter);
+            run(
+                &cache,
+                (old_dir, old_local_config.as_ref()),
+                (new_dir, new_local_config.as_ref()),
+                &mut stash,
+                &mut command_cache,
+                &mut emitter,
+            )?;
+
+            if did_create_cache {
+                cache::write_cache(&cache, &cache_path)?;
             }
+
+            command_cache.prune();
+            if command_cache.is_changed() {
+                command_cache.write(&command_cache_path)?;
+            }
+
+            if stash.is_changed() {
+                if stash.is_empty() {
+                    emitter.unset(STASH_VAR)?;
+                } else {
+                    emitter.export(STASH_VAR, &stash.to_json())?;
+                }
+            }
+
+            emitter.print();
         }
         cmd::Commands::Reload { shell } => {
             let config: Config = Config::from_config_file(&config_path)?;
             init_shell(Some(config), shell.unwrap())?;
         }
         cmd::Commands::Remove { shell } => remove_shell(shell.context("no shell passed")?)?,
+        cmd::Commands::Check => check(&config_path)?,
+        cmd::Commands::Migrate => migrate()?,
+        cmd::Commands::Profile { command } => match command {
+            ProfileCommands::Use { names } => use_profiles(&config_path, &names)?,
+            ProfileCommands::Clear => use_profiles(&config_path, "")?,
+            ProfileCommands::List => list_profiles(&config_path)?,
+        },
+        cmd::Commands::Encrypt {
+            file,
+            output,
+            recipients,
+        } => encrypt_file(&file, output, &recipients)?,
+        cmd::Commands::Decrypt { file, output } => decrypt_file(&file, output)?,
     }
 
     Ok(())
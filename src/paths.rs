@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Overrides where the global config is read from
+pub const CONFIG_VAR: &str = "CDWE_CONFIG";
+
+/// Overrides the profiles selected with `cdwe profile use`
+pub const PROFILE_VAR: &str = "CDWE_PROFILE";
+
+fn home() -> Result<PathBuf> {
+    Ok(PathBuf::from(
+        std::env::var("HOME").context("no $HOME set")?,
+    ))
+}
+
+fn to_string(path: PathBuf) -> Result<String> {
+    Ok(path
+        .to_str()
+        .with_context(|| format!("path is not valid unicode: {}", path.display()))?
+        .to_string())
+}
+
+/// Resolves an XDG base directory, relative values are invalid per the spec
+/// and fall back to the default under home
+fn xdg_dir(value: Option<String>, home: &Path, default: &str) -> PathBuf {
+    match value {
+        Some(value) if Path::new(&value).is_absolute() => PathBuf::from(value),
+        _ => home.join(default),
+    }
+}
+
+fn config_dir() -> Result<PathBuf> {
+    let dir = xdg_dir(std::env::var("XDG_CONFIG_HOME").ok(), &home()?, ".config");
+    Ok(dir.join("cdwe"))
+}
+
+fn cache_dir() -> Result<PathBuf> {
+    let dir = xdg_dir(std::env::var("XDG_CACHE_HOME").ok(), &home()?, ".cache");
+    Ok(dir.join("cdwe"))
+}
+
+fn data_dir() -> Result<PathBuf> {
+    let dir = xdg_dir(
+        std::env::var("XDG_DATA_HOME").ok(),
+        &home()?,
+        ".local/share",
+    );
+    Ok(dir.join("cdwe"))
+}
+
+fn state_dir() -> Result<PathBuf> {
+    let dir = xdg_dir(
+        std::env::var("XDG_STATE_HOME").ok(),
+        &home()?,
+        ".local/state",
+    );
+    Ok(dir.join("cdwe"))
+}
+
+/// `$XDG_CONFIG_HOME/cdwe/config.toml`
+pub fn xdg_config_path() -> Result<String> {
+    to_string(config_dir()?.join("config.toml"))
+}
+
+/// `~/cdwe.toml`, where versions before XDG support kept the config
+pub fn legacy_config_path() -> Result<String> {
+    to_string(home()?.join("cdwe.toml"))
+}
+
+/// The global config, from `$CDWE_CONFIG`, the XDG config dir or the legacy
+/// path in that order. New configs are created in the XDG config dir
+pub fn config_path() -> Result<String> {
+    if let Ok(path) = std::env::var(CONFIG_VAR) {
+        if !path.is_empty() {
+            return Ok(path);
+        }
+    }
+
+    let xdg = xdg_config_path()?;
+    let legacy = legacy_config_path()?;
+    if !Path::new(&xdg).exists() && Path::new(&legacy).exists() {
+        return Ok(legacy);
+    }
+
+    Ok(xdg)
+}
+
+pub fn cache_path() -> Result<String> {
+    to_string(cache_dir()?.join("cache.json"))
+}
+
+pub fn command_cache_path() -> Result<String> {
+    to_string(cache_dir()?.join("cmd_cache.json"))
+}
+
+/// Caches written by versions before XDG support, they are rebuilt on demand
+/// so migrating only removes them
+pub fn legacy_cache_paths() -> Result<Vec<String>> {
+    let home = home()?;
+    [".cdwe_cache.json", ".cdwe_cmd_cache.json"]
+        .iter()
+        .map(|name| to_string(home.join(name)))
+        .collect()
+}
+
+/// Holds the profiles selected with `cdwe profile use`
+pub fn profile_path() -> Result<String> {
+    to_string(state_dir()?.join("profile"))
+}
+
+pub fn key_path() -> Result<String> {
+    to_string(config_dir()?.join("key.txt"))
+}
+
+/// Where the script sourced by the shell config is installed
+pub fn shell_script_path(extension: &str) -> Result<String> {
+    to_string(data_dir()?.join(format!("cdwe.{}", extension)))
+}
+
+/// Where versions before XDG support installed the shell script
+pub fn legacy_shell_script_path(extension: &str) -> Result<String> {
+    to_string(home()?.join(format!(".cdwe.{}", extension)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::xdg_dir;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_xdg_dir() {
+        let home = Path::new("/home/user");
+        assert_eq!(
+            xdg_dir(Some("/xdg/config".to_string()), home, ".config"),
+            PathBuf::from("/xdg/config")
+        );
+        assert_eq!(
+            xdg_dir(None, home, ".config"),
+            PathBuf::from("/home/user/.config")
+        );
+        assert_eq!(
+            xdg_dir(Some("relative".to_string()), home, ".cache"),
+            PathBuf::from("/home/user/.cache")
+        );
+        assert_eq!(
+            xdg_dir(Some(String::new()), home, ".local/share"),
+            PathBuf::from("/home/user/.local/share")
+        );
+    }
+}
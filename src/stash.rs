@@ -0,0 +1,118 @@
+use crate::list::ListChange;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Name of the environment variable the stash is kept in between runs
+pub const STASH_VAR: &str = "__CDWE_STASH";
+
+/// Values variables had before cdwe first set them, so leaving a directory
+/// restores them instead of unsetting them
+/// List variables are modified in place, so instead of their value the stash
+/// holds what cdwe changed in them
+/// The stash is emitted as a json encoded environment variable and read back
+/// on the next run
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Stash {
+    values: BTreeMap<String, Option<String>>,
+    lists: BTreeMap<String, ListChange>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl Stash {
+    pub fn from_str(content: &str) -> Self {
+        serde_json::from_str(content).unwrap_or_default()
+    }
+
+    pub fn from_env() -> Self {
+        match std::env::var(STASH_VAR) {
+            Ok(content) => Stash::from_str(&content),
+            Err(_) => Stash::default(),
+        }
+    }
+
+    /// Remembers the current value of `name`, unless cdwe already holds on
+    /// to the value it had before it was first set
+    pub fn save(&mut self, name: &str) {
+        self.save_value(name, std::env::var(name).ok());
+    }
+
+    /// Remembers `value` as the value `name` had before cdwe set it, unless
+    /// cdwe already holds on to one
+    pub fn save_value(&mut self, name: &str, value: Option<String>) {
+        if !self.values.contains_key(name) {
+            self.values.insert(name.to_string(), value);
+            self.changed = true;
+        }
+    }
+
+    /// Remembers what cdwe changed in the list variable `name`
+    pub fn save_list(&mut self, name: &str, change: ListChange) {
+        self.lists.insert(name.to_string(), change);
+        self.changed = true;
+    }
+
+    /// Removes what cdwe changed in the list variable `name` from the stash
+    pub fn take_list(&mut self, name: &str) -> Option<ListChange> {
+        let change = self.lists.remove(name)?;
+        self.changed = true;
+        Some(change)
+    }
+
+    /// The value `name` had before cdwe first set it, or its current value if
+    /// cdwe hasn't set it yet
+    pub fn original(&self, name: &str) -> Option<String> {
+        match self.values.get(name) {
+            Some(value) => value.clone(),
+            None => std::env::var(name).ok(),
+        }
+    }
+
+    /// Removes `name` from the stash, returning the value it had before cdwe
+    /// set it, `None` if it wasn't set
+    pub fn take(&mut self, name: &str) -> Option<String> {
+        let value = self.values.remove(name)?;
+        self.changed = true;
+        value
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.lists.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stash;
+
+    #[test]
+    fn test_stash_round_trip() {
+        std::env::set_var("CDWE_STASH_TEST_SET", "production");
+        std::env::remove_var("CDWE_STASH_TEST_UNSET");
+
+        let mut stash = Stash::default();
+        stash.save("CDWE_STASH_TEST_SET");
+        stash.save("CDWE_STASH_TEST_UNSET");
+
+        // Only the first value is kept
+        std::env::set_var("CDWE_STASH_TEST_SET", "development");
+        stash.save("CDWE_STASH_TEST_SET");
+        assert!(stash.is_changed());
+
+        let mut stash = Stash::from_str(&stash.to_json());
+        assert_eq!(
+            stash.take("CDWE_STASH_TEST_SET").as_deref(),
+            Some("production")
+        );
+        assert_eq!(stash.take("CDWE_STASH_TEST_UNSET"), None);
+        assert!(stash.is_empty());
+    }
+}
@@ -1,4 +1,9 @@
+use crate::config::EnvVariable;
+use crate::list::is_list;
+use anyhow::{anyhow, Context, Result};
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
 
 pub fn get_content_hash(content: &str) -> String {
     let mut hasher = Sha256::new();
@@ -7,6 +12,312 @@ pub fn get_content_hash(content: &str) -> String {
     format!("{:?}", hasher.finalize())
 }
 
+/// Paths containing any glob special character are treated as patterns
+pub fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Returns the files an include pattern matches, sorted
+/// A path without glob characters always matches, even if it doesn't exist,
+/// so a missing include is reported when it's read
+pub fn resolve_include(pattern: &str) -> Result<Vec<PathBuf>> {
+    if !is_glob(pattern) {
+        return Ok(vec![PathBuf::from(pattern)]);
+    }
+
+    let mut paths = glob::glob(pattern)
+        .with_context(|| format!("Invalid glob in include: {}", pattern))?
+        .filter_map(|path| path.ok())
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Hashes the content of a config together with the content of every file
+/// its include patterns match, so editing, adding or removing an included
+/// file changes the hash
+pub fn get_config_hash(content: &str, includes: &[String]) -> Result<String> {
+    let mut combined = content.to_string();
+
+    for pattern in includes {
+        for path in resolve_include(pattern)? {
+            let included = std::fs::read_to_string(&path)
+                .with_context(|| format!("Could not read included config {}", path.display()))?;
+            combined.push_str(&format!("\n# include {}\n{}", path.display(), included));
+        }
+    }
+
+    Ok(get_content_hash(&combined))
+}
+
+fn is_var_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Resolves a variable by name, `None` if it isn't set
+pub type Lookup<'a> = dyn FnMut(&str) -> Result<Option<String>> + 'a;
+
+/// Resolves a single variable reference such as `NAME` or `NAME:-default`
+fn resolve_var(expr: &str, lookup: &mut Lookup) -> Result<String> {
+    let (name, default) = match expr.split_once(":-") {
+        Some((name, default)) => (name.trim(), Some(default)),
+        None => (expr.trim(), None),
+    };
+
+    if name.is_empty() || !name.chars().all(is_var_char) {
+        return Err(anyhow!("invalid variable name \"{}\"", name));
+    }
+
+    match (lookup(name)?, default) {
+        (Some(value), Some(default)) if value.is_empty() => expand_vars(default, lookup),
+        (Some(value), _) => Ok(value),
+        (None, Some(default)) => expand_vars(default, lookup),
+        (None, None) => Err(anyhow!("variable {} is not set", name)),
+    }
+}
+
+/// Expands `$VAR` and `${VAR}` references in `content`, with optional
+/// `${VAR:-default}` fallbacks
+///
+/// `\$` is kept as a literal `$`, and a `$` that doesn't start a
+/// reference is left untouched. Referencing a variable that `lookup` can't
+/// resolve and that has no default is an error
+pub fn expand_vars(content: &str, lookup: &mut Lookup) -> Result<String> {
+    let mut result = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(index) = rest.find(['$', '\\']) {
+        result.push_str(&rest[..index]);
+        let tail = &rest[index..];
+
+        if let Some(after) = tail.strip_prefix("\\$") {
+            result.push('$');
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix("${") {
+            let end = after
+                .find('}')
+                .ok_or_else(|| anyhow!("unterminated ${{ in \"{}\"", content))?;
+            result.push_str(&resolve_var(&after[..end], lookup)?);
+            rest = &after[end + 1..];
+        } else if let Some(after) = tail.strip_prefix('$') {
+            let end = match after.chars().next() {
+                Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                    after.find(|c| !is_var_char(c)).unwrap_or(after.len())
+                }
+                _ => 0,
+            };
+            if end == 0 {
+                result.push('$');
+            } else {
+                result.push_str(&resolve_var(&after[..end], lookup)?);
+            }
+            rest = &after[end..];
+        } else {
+            // A lone \ with nothing special after it
+            result.push_str(&tail[..1]);
+            rest = &tail[1..];
+        }
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Resolves the `$VAR` references in a set of variables
+/// References resolve to other variables in the same set first and then to
+/// the inherited environment. A variable referencing itself, like
+/// `PATH=$PATH:/bin`, refers to the inherited value
+/// The inherited environment is looked up through `inherited`, so values cdwe
+/// exported for the previous directory don't leak into the new ones
+/// Unlike dotenv-expand, a variable can also reference one defined after it,
+/// since sets merged from parent directories, local configs and .env files
+/// have no meaningful order. References to variables that aren't set expand
+/// to an empty string like they do in the shell
+struct Interpolator<'a> {
+    raw: HashMap<&'a str, &'a str>,
+    inherited: &'a dyn Fn(&str) -> Option<String>,
+    resolved: HashMap<String, String>,
+    stack: Vec<String>,
+}
+
+impl<'a> Interpolator<'a> {
+    fn resolve(&mut self, name: &str, raw: &str) -> Result<String> {
+        if let Some(value) = self.resolved.get(name) {
+            return Ok(value.clone());
+        }
+
+        self.stack.push(name.to_string());
+        let value = expand_vars(raw, &mut |reference| self.lookup(reference));
+        self.stack.pop();
+
+        let value = value.with_context(|| format!("Could not interpolate {}", name))?;
+        self.resolved.insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    fn lookup(&mut self, reference: &str) -> Result<Option<String>> {
+        if self.stack.last().map(|s| s.as_str()) == Some(reference) {
+            return Ok(Some((self.inherited)(reference).unwrap_or_default()));
+        }
+
+        if self.stack.iter().any(|s| s == reference) {
+            let cycle: Vec<&str> = self
+                .stack
+                .iter()
+                .skip_while(|s| *s != reference)
+                .map(|s| s.as_str())
+                .chain([reference])
+                .collect();
+            return Err(anyhow!("cycle between variables {}", cycle.join(" -> ")));
+        }
+
+        match self.raw.get(reference) {
+            Some(raw) => Ok(Some(self.resolve(reference, raw)?)),
+            None => Ok(Some((self.inherited)(reference).unwrap_or_default())),
+        }
+    }
+}
+
+/// Expands `$VAR`, `${VAR}` and `${VAR:-default}` in the values of
+/// `variables`, see [`Interpolator`] for how references are resolved
+/// `inherited` gives the value a variable has outside of cdwe
+/// List variables are only applied when they are exported, they are kept as
+/// they are and references to them resolve to the inherited environment
+/// Each variable is interpolated on its own, so one that can't be, like one
+/// in a cycle, doesn't affect the others
+pub fn interpolate_variables(
+    variables: &[EnvVariable],
+    inherited: &dyn Fn(&str) -> Option<String>,
+) -> Vec<Result<EnvVariable>> {
+    let mut interpolator = Interpolator {
+        inherited,
+        raw: variables
+            .iter()
+            .filter(|v| !is_list(v))
+            .map(|v| (v.name.as_str(), v.value.as_str()))
+            .collect(),
+        resolved: HashMap::new(),
+        stack: vec![],
+    };
+
+    variables
+        .iter()
+        .map(|var| {
+            if is_list(var) {
+                return Ok(var.clone());
+            }
+
+            Ok(EnvVariable {
+                name: var.name.clone(),
+                value: interpolator.resolve(&var.name, &var.value)?,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Looks up the home directory of `user` in /etc/passwd
+fn get_user_home(user: &str) -> Option<String> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.as_slice() {
+            [name, _, _, _, _, home, ..] if *name == user => Some(home.to_string()),
+            _ => None,
+        }
+    })
+}
+
+/// Expands a leading `~` or `~user` and any environment variable
+/// references in a configured path
+pub fn expand_path(path: &str) -> Result<String> {
+    let expand = || -> Result<String> {
+        let expanded = match path.strip_prefix('~') {
+            Some(rest) => {
+                let (user, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+                let home = if user.is_empty() {
+                    std::env::var("HOME").context("no $HOME set")?
+                } else {
+                    get_user_home(user).ok_or_else(|| anyhow!("unknown user {}", user))?
+                };
+                format!("{}{}", home, rest)
+            }
+            None => path.to_string(),
+        };
+
+        // {{VAR}} is the original path syntax, it's handled as ${VAR}
+        let re = regex::Regex::new(r"\{\{(.*?)\}\}").unwrap();
+        let expanded = re.replace_all(&expanded, |caps: &regex::Captures| {
+            format!("${{{}}}", caps[1].trim())
+        });
+
+        expand_vars(&expanded, &mut |name| Ok(std::env::var(name).ok()))
+    };
+
+    expand().with_context(|| format!("Could not expand path \"{}\"", path))
+}
+
+/// Lexically normalizes a path by removing `.` segments, empty segments and
+/// trailing slashes, and by resolving `..` against the preceding segment
+pub fn normalize_path(path: &str) -> String {
+    let is_absolute = path.starts_with('/');
+    let mut segments: Vec<&str> = vec![];
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => match segments.last() {
+                Some(&last) if last != ".." => {
+                    segments.pop();
+                }
+                // `..` can't go above the root
+                _ if is_absolute => {}
+                _ => segments.push(segment),
+            },
+            _ => segments.push(segment),
+        }
+    }
+
+    let joined = segments.join("/");
+    match (is_absolute, joined.is_empty()) {
+        (true, _) => format!("/{}", joined),
+        (false, true) => ".".to_string(),
+        (false, false) => joined,
+    }
+}
+
+/// Normalizes a path and, if `resolve_symlinks` is set, resolves it to its
+/// canonical location. Paths that don't exist are only normalized
+pub fn resolve_path(path: &str, resolve_symlinks: bool) -> String {
+    let normalized = normalize_path(path);
+
+    if !resolve_symlinks {
+        return normalized;
+    }
+
+    std::fs::canonicalize(&normalized)
+        .ok()
+        .and_then(|canonical| canonical.to_str().map(|c| c.to_string()))
+        .unwrap_or(normalized)
+}
+
+/// Resolves a glob like [`resolve_path`], only the directories before the
+/// first wildcard are resolved since the ones it matches aren't known yet
+pub fn resolve_glob(pattern: &str, resolve_symlinks: bool) -> String {
+    let normalized = normalize_path(pattern);
+    let wildcard = normalized.find(['*', '?', '[']).unwrap_or(normalized.len());
+
+    match normalized[..wildcard].rfind('/') {
+        Some(end) if resolve_symlinks && end > 0 => format!(
+            "{}{}",
+            resolve_path(&normalized[..end], true),
+            &normalized[end..]
+        ),
+        _ => normalized,
+    }
+}
+
 pub fn trim_quotes(s: &str) -> String {
     if s.len() < 2 {
         return s.to_string();
@@ -29,4 +340,131 @@ mod tests {
         assert_eq!(trim_quotes("\"test"), "\"test");
         assert_eq!(trim_quotes("test'"), "test'");
     }
+
+    #[test]
+    fn test_interpolate_variables() {
+        use super::interpolate_variables;
+        use crate::config::EnvVariable;
+
+        std::env::set_var("CDWE_INTERPOLATE_HOST", "localhost");
+        std::env::set_var("CDWE_INTERPOLATE_PATH", "/bin");
+        let vars = |pairs: &[(&str, &str)]| -> Vec<EnvVariable> {
+            pairs
+                .iter()
+                .map(|(name, value)| EnvVariable {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                    ..Default::default()
+                })
+                .collect()
+        };
+
+        let env = |name: &str| std::env::var(name).ok();
+        let interpolated = interpolate_variables(
+            &vars(&[
+                ("URL", "postgres://${DB_USER}@$CDWE_INTERPOLATE_HOST/app"),
+                ("DB_USER", "${CDWE_INTERPOLATE_USER:-admin}"),
+                ("CDWE_INTERPOLATE_PATH", "$CDWE_INTERPOLATE_PATH:/opt/bin"),
+                ("PRICE", "\\$5"),
+                ("MISSING", "a${CDWE_DOES_NOT_EXIST}b"),
+            ]),
+            &env,
+        )
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+        assert_eq!(
+            interpolated,
+            vars(&[
+                ("URL", "postgres://admin@localhost/app"),
+                ("DB_USER", "admin"),
+                ("CDWE_INTERPOLATE_PATH", "/bin:/opt/bin"),
+                ("PRICE", "$5"),
+                ("MISSING", "ab"),
+            ])
+        );
+
+        let cycle = interpolate_variables(
+            &vars(&[("A", "$B"), ("B", "x${C}"), ("C", "$A"), ("D", "fine")]),
+            &env,
+        );
+        assert!(format!("{:#}", cycle[0].as_ref().unwrap_err()).contains("A -> B -> C -> A"));
+        assert!(cycle[1].is_err() && cycle[2].is_err());
+        assert_eq!(cycle[3].as_ref().unwrap().value, "fine");
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        use super::normalize_path;
+        assert_eq!(normalize_path("/dev/project/"), "/dev/project");
+        assert_eq!(normalize_path("/dev//project/./src/.."), "/dev/project");
+        assert_eq!(normalize_path("/dev/../../project"), "/project");
+        assert_eq!(normalize_path("/"), "/");
+        assert_eq!(normalize_path("//"), "/");
+        assert_eq!(normalize_path("dev/../.."), "..");
+        assert_eq!(normalize_path("./"), ".");
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        use super::{resolve_glob, resolve_path};
+        let dir = std::env::temp_dir().join(format!("cdwe-resolve-{}", std::process::id()));
+        let target = dir.join("target");
+        let link = dir.join("link");
+        std::fs::create_dir_all(&target).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let target = std::fs::canonicalize(&target).unwrap();
+        let link = format!("{}/", link.to_str().unwrap());
+        assert_eq!(resolve_path(&link, true), target.to_str().unwrap());
+        assert_eq!(resolve_path(&link, false), link.trim_end_matches('/'));
+        assert_eq!(resolve_path("/does/not/../exist", true), "/does/exist");
+
+        let glob = format!("{}/*/services/*", link.trim_end_matches('/'));
+        assert_eq!(
+            resolve_glob(&glob, true),
+            format!("{}/*/services/*", target.to_str().unwrap())
+        );
+        assert_eq!(resolve_glob(&glob, false), glob);
+        assert_eq!(resolve_glob("/*/src", true), "/*/src");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_path() {
+        use super::expand_path;
+        std::env::set_var("TEST_HOME", "/home/user");
+        std::env::set_var("TEST_NAME", "testing");
+        std::env::set_var("TEST_EMPTY", "");
+        assert_eq!(
+            expand_path("{{TEST_HOME}}/testing").unwrap(),
+            "/home/user/testing"
+        );
+        assert_eq!(
+            expand_path("{{TEST_HOME}}/{{TEST_NAME}}").unwrap(),
+            "/home/user/testing"
+        );
+        assert!(expand_path("{{DOES_NOT_EXIST}}/{{TEST_NAME}}").is_err());
+        assert_eq!(
+            expand_path("/home/user/testing").unwrap(),
+            "/home/user/testing"
+        );
+        assert_eq!(expand_path("$TEST_HOME/x").unwrap(), "/home/user/x");
+        assert_eq!(expand_path("${TEST_HOME}x").unwrap(), "/home/userx");
+        assert_eq!(expand_path("${DOES_NOT_EXIST:-/opt}/x").unwrap(), "/opt/x");
+        assert_eq!(
+            expand_path("{{ DOES_NOT_EXIST:-/opt }}/{{TEST_EMPTY:-y}}").unwrap(),
+            "/opt/y"
+        );
+        assert_eq!(expand_path("/a/$/b").unwrap(), "/a/$/b");
+        assert_eq!(expand_path("/a/$5").unwrap(), "/a/$5");
+        assert!(expand_path("${TEST_HOME").is_err());
+
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(expand_path("~").unwrap(), home);
+        assert_eq!(expand_path("~/dev").unwrap(), format!("{}/dev", home));
+        assert_eq!(expand_path("~root/dev").unwrap(), "/root/dev");
+        assert!(expand_path("~no-such-user-cdwe/dev").is_err());
+    }
 }
@@ -0,0 +1,399 @@
+use crate::config::EnvVariable;
+use crate::utils::expand_path;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Seconds a `value_from_cmd` command may run when no timeout is configured
+const DEFAULT_TIMEOUT: u64 = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct CachedValue {
+    value: String,
+    /// Unix timestamp in seconds after which the command has to run again
+    expires_at: u64,
+}
+
+/// Output of `value_from_cmd` commands that have a `cache_ttl`, keyed by the
+/// directory they ran in and the command
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(transparent)]
+pub struct CommandCache {
+    values: BTreeMap<String, CachedValue>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn cache_key(dir: &str, command: &str) -> String {
+    format!("{}\n{}", dir, command)
+}
+
+impl CommandCache {
+    pub fn from_str(content: &str) -> Self {
+        serde_json::from_str(content).unwrap_or_default()
+    }
+
+    fn get(&self, dir: &str, command: &str, now: u64) -> Option<&str> {
+        self.values
+            .get(&cache_key(dir, command))
+            .filter(|cached| now < cached.expires_at)
+            .map(|cached| cached.value.as_str())
+    }
+
+    fn insert(&mut self, dir: &str, command: &str, value: &str, expires_at: u64) {
+        self.values.insert(
+            cache_key(dir, command),
+            CachedValue {
+                value: value.to_string(),
+                expires_at,
+            },
+        );
+        self.changed = true;
+    }
+
+    /// Drops expired entries so the file doesn't grow with every directory
+    /// ever visited
+    pub fn prune(&mut self) {
+        let now = now();
+        let len = self.values.len();
+        self.values.retain(|_, cached| now < cached.expires_at);
+        self.changed |= self.values.len() != len;
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        if let Some(parent) = Path::new(path).parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {}", parent.display()))?;
+        }
+
+        std::fs::write(path, self.to_json()).with_context(|| format!("Could not write {}", path))
+    }
+}
+
+/// Reads `pipe` to the end on its own thread, the output is sent once the
+/// pipe is closed
+fn read_pipe(mut pipe: impl Read + Send + 'static) -> Receiver<std::io::Result<String>> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        let _ = sender.send(pipe.read_to_string(&mut output).map(|_| output));
+    });
+    receiver
+}
+
+/// Runs `command` with `sh` in `dir` and returns its trimmed output
+/// The command is killed if it runs for longer than `timeout` seconds
+fn run_command(command: &str, dir: &str, timeout: u64) -> Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("could not run \"{}\"", command))?;
+
+    // Read the output while waiting so a command with a lot of output can't
+    // block on a full pipe
+    let stdout = read_pipe(child.stdout.take().context("no stdout")?);
+    let stderr = read_pipe(child.stderr.take().context("no stderr")?);
+
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    let timed_out = || anyhow!("\"{}\" timed out after {} seconds", command, timeout);
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    // Processes the command left running in the background keep the pipes
+    // open after it exits, so the output is only waited for until the deadline
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    let output = match stdout.recv_timeout(remaining()) {
+        Ok(output) => output?,
+        Err(RecvTimeoutError::Timeout) => return Err(timed_out()),
+        Err(RecvTimeoutError::Disconnected) => {
+            return Err(anyhow!("could not read output of \"{}\"", command))
+        }
+    };
+    if !status.success() {
+        let error = stderr
+            .recv_timeout(remaining())
+            .ok()
+            .and_then(|e| e.ok())
+            .unwrap_or_default();
+        let error = match error.trim() {
+            "" => String::new(),
+            error => format!(": {}", error),
+        };
+        return Err(anyhow!("\"{}\" failed with {}{}", command, status, error));
+    }
+
+    Ok(output.trim().to_string())
+}
+
+/// Reads the trimmed content of `path`, relative to `dir`
+/// Secrets are expected to be readable only by their owner, so files anyone can
+/// read are refused unless `allow_world_readable` is set
+fn read_value_file(path: &str, dir: &str, allow_world_readable: bool) -> Result<String> {
+    let path = Path::new(dir).join(expand_path(path)?);
+
+    #[cfg(unix)]
+    if !allow_world_readable {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(&path)
+            .with_context(|| format!("could not read {}", path.display()))?
+            .permissions()
+            .mode();
+        if mode & 0o004 != 0 {
+            return Err(anyhow!(
+                "{} is readable by everyone, restrict it with `chmod o-r` or set allow_world_readable",
+                path.display()
+            ));
+        }
+    }
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("could not read {}", path.display()))?;
+    Ok(content.trim().to_string())
+}
+
+/// Whether the value of `var` is only known once cdwe runs
+fn is_computed(var: &EnvVariable) -> bool {
+    var.value_from_cmd.is_some() || var.value_from_file.is_some()
+}
+
+/// Keeps a computed value literal when the variables are interpolated
+fn escape(value: &str) -> String {
+    value.replace('$', "\\$")
+}
+
+/// Replaces the value of every variable with a `value_from_cmd` by the output
+/// of the command run in `dir`, and the value of every variable with a
+/// `value_from_file` by the content of the file
+/// Output is reused from `cache` while it's younger than the `cache_ttl` of the
+/// variable. Variables that can't be computed are left out with a warning
+pub fn resolve_values(
+    variables: Vec<EnvVariable>,
+    dir: &str,
+    cache: &mut CommandCache,
+) -> Vec<EnvVariable> {
+    let now = now();
+
+    variables
+        .into_iter()
+        .filter_map(|mut var| {
+            if let Some(path) = var.value_from_file.as_deref() {
+                return match read_value_file(path, dir, var.allow_world_readable) {
+                    Ok(value) => {
+                        var.value = escape(&value);
+                        Some(var)
+                    }
+                    Err(err) => {
+                        eprintln!("WARNING: could not read {}: {}", var.name, err);
+                        None
+                    }
+                };
+            }
+
+            let Some(command) = var.value_from_cmd.as_deref() else {
+                return Some(var);
+            };
+
+            let cached = var
+                .cache_ttl
+                .and_then(|_| cache.get(dir, command, now))
+                .map(|value| value.to_string());
+            let value = match cached {
+                Some(value) => value,
+                None => {
+                    let timeout = var.timeout.unwrap_or(DEFAULT_TIMEOUT);
+                    match run_command(command, dir, timeout) {
+                        Ok(value) => {
+                            if let Some(ttl) = var.cache_ttl {
+                                cache.insert(dir, command, &value, now + ttl);
+                            }
+                            value
+                        }
+                        Err(err) => {
+                            eprintln!("WARNING: could not compute {}: {}", var.name, err);
+                            return None;
+                        }
+                    }
+                }
+            };
+
+            var.value = escape(&value);
+            Some(var)
+        })
+        .collect()
+}
+
+/// Replaces the value of every computed variable by the value it currently
+/// has, so leaving a directory doesn't run its commands or read its files again
+/// Variables that aren't set are left out, they couldn't be computed
+pub fn current_values(variables: Vec<EnvVariable>) -> Vec<EnvVariable> {
+    variables
+        .into_iter()
+        .filter_map(|mut var| {
+            if is_computed(&var) {
+                var.value = escape(&std::env::var(&var.name).ok()?);
+            }
+            Some(var)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_values, CommandCache};
+    use crate::config::EnvVariable;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_resolve_values() {
+        let dir = std::env::temp_dir();
+        let dir = dir.to_str().unwrap();
+        let var = |name: &str, command: &str| EnvVariable {
+            name: name.to_string(),
+            value_from_cmd: Some(command.to_string()),
+            ..Default::default()
+        };
+        let mut cache = CommandCache::default();
+
+        let start = std::time::Instant::now();
+        let resolved = resolve_values(
+            vec![
+                var("DIR", "pwd"),
+                var("PRICE", "echo ' $5 '"),
+                var("FAILED", "exit 1"),
+                EnvVariable {
+                    timeout: Some(0),
+                    ..var("SLOW", "sleep 5")
+                },
+                EnvVariable {
+                    timeout: Some(1),
+                    ..var("BACKGROUND", "sleep 5 & echo hi")
+                },
+                EnvVariable {
+                    name: "PLAIN".to_string(),
+                    value: "value".to_string(),
+                    ..Default::default()
+                },
+            ],
+            dir,
+            &mut cache,
+        );
+        let values: Vec<(&str, &str)> = resolved
+            .iter()
+            .map(|v| (v.name.as_str(), v.value.as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("DIR", std::fs::canonicalize(dir).unwrap().to_str().unwrap()),
+                ("PRICE", "\\$5"),
+                ("PLAIN", "value"),
+            ]
+        );
+        assert!(start.elapsed() < std::time::Duration::from_secs(4));
+        assert!(!cache.is_changed());
+    }
+
+    #[test]
+    fn test_resolve_values_cached() {
+        let dir = std::env::temp_dir();
+        let dir = dir.to_str().unwrap();
+        let cached = EnvVariable {
+            name: "CACHED".to_string(),
+            value_from_cmd: Some("date +%N".to_string()),
+            cache_ttl: Some(60),
+            ..Default::default()
+        };
+        let mut cache = CommandCache::default();
+
+        let first = resolve_values(vec![cached.clone()], dir, &mut cache);
+        assert!(cache.is_changed());
+
+        let mut cache = CommandCache::from_str(&cache.to_json());
+        let second = resolve_values(vec![cached], dir, &mut cache);
+        assert_eq!(first, second);
+        assert!(!cache.is_changed());
+
+        cache.prune();
+        assert!(!cache.is_changed());
+
+        cache.insert(dir, "date +%N", "expired", 0);
+        cache.changed = false;
+        cache.prune();
+        assert!(cache.is_changed());
+        assert!(cache.values.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_values_from_file() {
+        let dir = std::env::temp_dir().join("cdwe_test_value_from_file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, content: &str, mode: u32| {
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        };
+        write("token", "  s3cr$t\n", 0o600);
+        write("public", "visible", 0o644);
+
+        let var = |name: &str, file: &str| EnvVariable {
+            name: name.to_string(),
+            value_from_file: Some(file.to_string()),
+            ..Default::default()
+        };
+        let resolved = resolve_values(
+            vec![
+                var("TOKEN", "token"),
+                var("PUBLIC", "public"),
+                EnvVariable {
+                    allow_world_readable: true,
+                    ..var("ALLOWED", dir.join("public").to_str().unwrap())
+                },
+                var("MISSING", "missing"),
+            ],
+            dir.to_str().unwrap(),
+            &mut CommandCache::default(),
+        );
+        let values: Vec<(&str, &str)> = resolved
+            .iter()
+            .map(|v| (v.name.as_str(), v.value.as_str()))
+            .collect();
+        assert_eq!(values, vec![("TOKEN", "s3cr\\$t"), ("ALLOWED", "visible")]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}